@@ -6,8 +6,8 @@ use chrono::prelude::*;
 use std::fs;
 use std::io::{BufRead, BufReader};
 
-fn main() {
-    let sc = Context::new("local");
+fn main() -> Result<()> {
+    let sc = Context::new("local")?;
     let files = fs::read_dir("csv_folder")
         .unwrap()
         .map(|x| x.unwrap().path().to_str().unwrap().to_owned())
@@ -31,7 +31,8 @@ fn main() {
     }));
     let sum = line.reduce_by_key(Fn!(|((vl, cl), (vr, cr))| (vl + vr, cl + cr)), 1);
     let avg = sum.map(Fn!(|(k, (v, c))| (k, v as f64 / c)));
-    let res = avg.collect();
+    let res = avg.collect()?;
     println!("{:?}", &res[0]);
     sc.drop_executors();
+    Ok(())
 }
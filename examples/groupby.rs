@@ -1,8 +1,8 @@
 #![allow(where_clauses_object_safety)]
 use native_spark::*;
 
-fn main() {
-    let sc = Context::new("local");
+fn main() -> Result<()> {
+    let sc = Context::new("local")?;
     let vec = vec![
         ("x".to_string(), 1),
         ("x".to_string(), 2),
@@ -22,7 +22,8 @@ fn main() {
     ];
     let r = sc.make_rdd(vec, 4);
     let g = r.group_by_key(4);
-    let res = g.collect();
+    let res = g.collect()?;
     println!("res {:?}", res);
     sc.drop_executors();
+    Ok(())
 }
@@ -1,8 +1,8 @@
 #![allow(where_clauses_object_safety)]
 use native_spark::*;
 
-fn main() {
-    let sc = Context::new("local");
+fn main() -> Result<()> {
+    let sc = Context::new("local")?;
     let col1 = vec![
         (1, ("A".to_string(), "B".to_string())),
         (2, ("C".to_string(), "D".to_string())),
@@ -20,6 +20,7 @@ fn main() {
     ];
     let col2 = sc.parallelize(col2, 4);
     let inner_joined_rdd = col2.join(col1.clone(), 4);
-    let res = inner_joined_rdd.collect();
+    let res = inner_joined_rdd.collect()?;
     println!("res {:?}", res);
+    Ok(())
 }
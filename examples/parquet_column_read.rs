@@ -12,8 +12,8 @@ use std::fs;
 use std::fs::File;
 use std::path::Path;
 
-fn main() {
-    let sc = Context::new("local");
+fn main() -> Result<()> {
+    let sc = Context::new("local")?;
     let files = fs::read_dir("parquet_file_dir")
         .unwrap()
         .map(|x| x.unwrap().path().to_str().unwrap().to_owned())
@@ -23,9 +23,10 @@ fn main() {
     let read = files.flat_map(Fn!(|file| read(file)));
     let sum = read.reduce_by_key(Fn!(|((vl, cl), (vr, cr))| (vl + vr, cl + cr)), 1);
     let avg = sum.map(Fn!(|(k, (v, c))| (k, v as f64 / c)));
-    let res = avg.collect();
+    let res = avg.collect()?;
     println!("{:?}", &res[0]);
     sc.drop_executors();
+    Ok(())
 }
 
 fn read(file: String) -> Box<dyn Iterator<Item = ((i32, String, i64), (i64, f64))>> {
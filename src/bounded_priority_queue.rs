@@ -0,0 +1,94 @@
+use std::cmp::Ordering;
+
+// Priority queue which keeps only the `max_size` smallest elements according to the given comparator.
+// Used by take_ordered and top, so that each partition sends at most `max_size` elements to the driver.
+// Internally it is a binary max heap, so the element to be evicted is always at the root.
+pub struct BoundedPriorityQueue<T, F>
+where
+    F: Fn(&T, &T) -> Ordering,
+{
+    max_size: usize,
+    cmp: F,
+    heap: Vec<T>,
+}
+
+impl<T, F> BoundedPriorityQueue<T, F>
+where
+    F: Fn(&T, &T) -> Ordering,
+{
+    pub fn new(max_size: usize, cmp: F) -> Self {
+        BoundedPriorityQueue {
+            max_size,
+            cmp,
+            heap: Vec::with_capacity(max_size),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.heap.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.heap.is_empty()
+    }
+
+    pub fn push(&mut self, item: T) {
+        if self.max_size == 0 {
+            return;
+        }
+        if self.heap.len() < self.max_size {
+            self.heap.push(item);
+            self.sift_up(self.heap.len() - 1);
+        } else if (self.cmp)(&item, &self.heap[0]) == Ordering::Less {
+            self.heap[0] = item;
+            self.sift_down(0);
+        }
+    }
+
+    pub fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for item in iter {
+            self.push(item);
+        }
+    }
+
+    /// Consumes the queue and returns the retained elements in ascending order of the comparator.
+    pub fn into_sorted_vec(self) -> Vec<T> {
+        let BoundedPriorityQueue { cmp, mut heap, .. } = self;
+        heap.sort_by(|a, b| cmp(a, b));
+        heap
+    }
+
+    fn sift_up(&mut self, mut pos: usize) {
+        while pos > 0 {
+            let parent = (pos - 1) / 2;
+            if (self.cmp)(&self.heap[pos], &self.heap[parent]) != Ordering::Greater {
+                break;
+            }
+            self.heap.swap(pos, parent);
+            pos = parent;
+        }
+    }
+
+    fn sift_down(&mut self, mut pos: usize) {
+        let len = self.heap.len();
+        loop {
+            let left = 2 * pos + 1;
+            let right = left + 1;
+            let mut largest = pos;
+            if left < len && (self.cmp)(&self.heap[left], &self.heap[largest]) == Ordering::Greater
+            {
+                largest = left;
+            }
+            if right < len
+                && (self.cmp)(&self.heap[right], &self.heap[largest]) == Ordering::Greater
+            {
+                largest = right;
+            }
+            if largest == pos {
+                break;
+            }
+            self.heap.swap(pos, largest);
+            pos = largest;
+        }
+    }
+}
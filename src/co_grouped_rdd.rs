@@ -1,6 +1,5 @@
 use super::*;
 use std::any::Any;
-use std::collections::HashMap;
 use std::hash::Hash;
 use std::hash::Hasher;
 use std::marker::PhantomData;
@@ -92,7 +91,7 @@ impl<K: Data + Eq + Hash> CoGroupedRdd<K> {
             let part = part.clone();
             if rdd
                 .partitioner()
-                .map_or(false, |p| p.equals(&part as &dyn Any))
+                .map_or(false, |p| p.equals((*part).as_any()))
             {
                 let rdd_base = rdd.clone().into();
                 deps.push(Dependency::OneToOneDependency(
@@ -109,6 +108,7 @@ impl<K: Data + Eq + Hash> CoGroupedRdd<K> {
                         rdd_base,
                         aggr.clone(),
                         part,
+                        false,
                     )) as Arc<dyn ShuffleDependencyTrait>,
                 ))
                 //                deps.push(Arc::new(OneToOneDependencyVals::new(rdd)))
@@ -116,7 +116,7 @@ impl<K: Data + Eq + Hash> CoGroupedRdd<K> {
         }
         //        vals.dependencies = deps;
         vals.dependencies = deps;
-        let vals = Arc::new(vals);
+        let vals = Arc::new(vals.track_shuffles());
         CoGroupedRdd {
             vals,
             //                context,
@@ -147,7 +147,7 @@ impl<K: Data + Eq + Hash> RddBase for CoGroupedRdd<K> {
                 self.rdds
                     .iter()
                     .enumerate()
-                    .map(|(i, r)| match &self.get_dependencies()[i] {
+                    .map(|(rdd_index, r)| match &self.get_dependencies()[rdd_index] {
                         Dependency::ShuffleDependency(s) => {
                             CoGroupSplitDep::ShuffleCoGroupSplitDep {
                                 shuffle_id: s.get_shuffle_id(),
@@ -171,14 +171,19 @@ impl<K: Data + Eq + Hash> RddBase for CoGroupedRdd<K> {
         Some(part)
     }
     fn iterator_any(&self, split: Box<dyn Split>) -> Box<dyn Iterator<Item = Box<dyn AnyData>>> {
+        Box::new(
+            self.iterator(split)
+                .map(|(k, v)| Box::new((k, v)) as Box<dyn AnyData>),
+        )
+    }
+    fn cogroup_iterator_any(
+        &self,
+        split: Box<dyn Split>,
+    ) -> Box<dyn Iterator<Item = Box<dyn AnyData>>> {
         Box::new(
             self.iterator(split)
                 .map(|(k, v)| Box::new((k, Box::new(v) as Box<dyn AnyData>)) as Box<dyn AnyData>),
         )
-        //        Box::new(
-        //            self.iterator(split)
-        //                .map(|x| Box::new(x) as Box<dyn AnyData>),
-        //        )
     }
 }
 impl<K: Data + Eq + Hash> Rdd<(K, Vec<Vec<Box<dyn AnyData>>>)> for CoGroupedRdd<K> {
@@ -193,49 +198,52 @@ impl<K: Data + Eq + Hash> Rdd<(K, Vec<Vec<Box<dyn AnyData>>>)> for CoGroupedRdd<
         split: Box<dyn Split>,
     ) -> Box<dyn Iterator<Item = (K, Vec<Vec<Box<dyn AnyData>>>)>> {
         if let Ok(split) = split.downcast::<CoGroupSplit>() {
-            let mut agg: HashMap<K, Vec<Vec<Box<dyn AnyData>>>> = HashMap::new();
+            let num_rdds = self.rdds.len();
+            // Values of each key are grouped per parent rdd. The map spills to disk when it grows beyond the memory threshold.
+            let mut agg = ExternalAppendOnlyMap::new(
+                |mut groups: Vec<Vec<Box<dyn AnyData>>>, other: Vec<Vec<Box<dyn AnyData>>>| {
+                    for (group, values) in groups.iter_mut().zip(other) {
+                        group.extend(values);
+                    }
+                    groups
+                },
+                env::config.shuffle_spill_memory,
+            );
             for (dep_num, dep) in split.clone().deps.into_iter().enumerate() {
                 match dep {
                     CoGroupSplitDep::NarrowCoGroupSplitDep { rdd, split } => {
                         info!("inside iterator cogrouprdd  narrow dep");
-                        for i in rdd.iterator_any(split) {
-                            info!(
-                                "inside iterator cogrouprdd  narrow dep iterator any {:?}",
-                                i
-                            );
-                            let b = i
-                                .into_any()
-                                .downcast::<(Box<dyn AnyData>, Box<dyn AnyData>)>()
-                                .unwrap();
+                        // The parent is already partitioned the same way, so its split can be read directly.
+                        for i in rdd.cogroup_iterator_any(split) {
+                            let b = i.into_any().downcast::<(K, Box<dyn AnyData>)>().unwrap();
                             let (k, v) = *b;
-                            let k = *(k.into_any().downcast::<K>().unwrap());
-                            agg.entry(k)
-                                .or_insert_with(|| vec![Vec::new(); self.rdds.len()])[dep_num]
-                                .push(v)
+                            let mut groups = vec![Vec::new(); num_rdds];
+                            groups[dep_num].push(v);
+                            agg.insert(k, groups);
                         }
                     }
                     CoGroupSplitDep::ShuffleCoGroupSplitDep { shuffle_id } => {
-                        info!("inside iterator cogrouprdd  shuffle dep agg {:?}", agg);
+                        info!("inside iterator cogrouprdd  shuffle dep");
                         let merge_pair = |(k, c): (K, Vec<Box<dyn AnyData>>)| {
-                            let temp = agg
-                                .entry(k)
-                                .or_insert_with(|| vec![Vec::new(); self.rdds.len()]);
-                            for v in c {
-                                temp[dep_num].push(v);
-                            }
+                            let mut groups = vec![Vec::new(); num_rdds];
+                            groups[dep_num] = c;
+                            agg.insert(k, groups);
                         };
                         let fetcher = ShuffleFetcher;
 
-                        fetcher.fetch(
+                        if let Err(failed) = fetcher.fetch(
                             self.vals.context.clone(),
                             shuffle_id,
                             split.get_index(),
                             merge_pair,
-                        );
+                        ) {
+                            // aborts the task, the scheduler recomputes the missing map output
+                            std::panic::resume_unwind(Box::new(failed));
+                        }
                     }
                 }
             }
-            Box::new(agg.into_iter().map(|(k, v)| (k, v)))
+            agg.into_combined_iter()
         } else {
             panic!("Got split object from different concrete type other than CoGroupSplit")
         }
@@ -1,17 +1,17 @@
 use super::*;
-use capnp::serialize_packed;
+use parking_lot::Mutex;
 use simplelog::*;
-//use parking_lot::Mutex;
 //use serde_derive;
 //use std::collections::HashMap;
 use std::fs::File;
 //use std::io::prelude::*;
 //use std::net::TcpListener;
-use std::net::TcpStream;
+use std::net::Ipv4Addr;
 use std::ops::Range;
 //use std::option::Iter;
 use std::process::Command;
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Sender};
 use std::sync::Arc;
 //use std::sync::Mutex;
 //use std::thread;
@@ -45,23 +45,61 @@ impl Default for Schedulers {
 impl Schedulers {
     pub fn run_job<T: Data, U: Data, F, RT>(
         &mut self,
+        run_id: usize,
         func: Arc<F>,
         final_rdd: Arc<RT>,
         partitions: Vec<usize>,
         allow_local: bool,
-    ) -> Vec<U>
+    ) -> Result<Vec<U>>
     where
         F: SerFunc((TasKContext, Box<dyn Iterator<Item = T>>)) -> U,
         RT: Rdd<T> + 'static,
     {
         use Schedulers::*;
         match self {
-            Distributed(local) => local.run_job(func, final_rdd, partitions, allow_local),
-            Local(local) => local.run_job(func, final_rdd, partitions, allow_local),
+            Distributed(distributed) => {
+                distributed.run_job(run_id, func, final_rdd, partitions, allow_local)
+            }
+            Local(local) => local.run_job(run_id, func, final_rdd, partitions, allow_local),
+        }
+    }
+
+    pub fn cancel_job(&self, job_id: usize) {
+        use Schedulers::*;
+        match self {
+            Distributed(distributed) => distributed.cancel_job(job_id),
+            Local(local) => local.cancel_job(job_id),
+        }
+    }
+
+    pub fn job_progress(&self, job_id: usize) -> Option<JobProgress> {
+        use Schedulers::*;
+        match self {
+            Distributed(distributed) => distributed.job_progress(job_id),
+            Local(local) => local.job_progress(job_id),
+        }
+    }
+
+    pub fn active_jobs(&self) -> Vec<usize> {
+        use Schedulers::*;
+        match self {
+            Distributed(distributed) => distributed.active_jobs(),
+            Local(local) => local.active_jobs(),
+        }
+    }
+
+    pub fn remove_shuffle(&self, shuffle_id: usize) {
+        use Schedulers::*;
+        match self {
+            Distributed(distributed) => distributed.remove_shuffle(shuffle_id),
+            Local(local) => local.remove_shuffle(shuffle_id),
         }
     }
 }
 
+// Number of contexts created in this process which are not shut down yet.
+static active_contexts: AtomicUsize = AtomicUsize::new(0);
+
 #[derive(Clone, Default)]
 pub struct Context {
     next_rdd_id: Arc<AtomicUsize>,
@@ -69,6 +107,8 @@ pub struct Context {
     scheduler: Schedulers,
     address_map: Vec<(String, usize)>,
     distributed_master: bool,
+    // Queue of the shuffles to clean up, drained by a single thread started with the first cleanup.
+    shuffle_cleaner: Arc<Mutex<Option<Sender<usize>>>>,
 }
 
 #[derive(Deserialize)]
@@ -79,7 +119,13 @@ struct Hosts {
 
 impl Context {
     // Sends the binary to all nodes present in hosts.conf and starts them
-    pub fn new(mode: &str) -> Self {
+    pub fn new(mode: &str) -> Result<Self> {
+        let context = Context::init(mode)?;
+        active_contexts.fetch_add(1, Ordering::SeqCst);
+        Ok(context)
+    }
+
+    fn init(mode: &str) -> Result<Self> {
         let next_rdd_id = Arc::new(AtomicUsize::new(0));
         let next_shuffle_id = Arc::new(AtomicUsize::new(0));
         use Schedulers::*;
@@ -99,22 +145,22 @@ impl Context {
                             WriteLogger::new(
                                 LevelFilter::Info,
                                 Config::default(),
-                                File::create(format!("/tmp/executor-{}", uuid))
-                                    .expect("not able to create log file"),
+                                File::create(format!("/tmp/executor-{}", uuid))?,
                             ),
                         ]);
                         info!("started client");
-                        let mut host_file =
-                            File::open("hosts.conf").expect("Unable to open the file");
+                        let mut host_file = File::open("hosts.conf")?;
                         let mut hosts = String::new();
-                        host_file
-                            .read_to_string(&mut hosts)
-                            .expect("Unable to read the file");
+                        host_file.read_to_string(&mut hosts)?;
                         //                        let hosts: Hosts =
                         //                            toml::from_str(&hosts).expect("unable to process the hosts.conf file");
-                        let executor = Executor::new(
-                            args[1].parse().expect("problem with executor arguments"),
-                        );
+                        let port =
+                            args.get(1)
+                                .and_then(|port| port.parse().ok())
+                                .ok_or_else(|| {
+                                    Error::Config("problem with executor arguments".to_string())
+                                })?;
+                        let executor = Executor::new(port);
                         executor.worker();
                         info!("initiated executor worker exit");
                         executor.exit_signal();
@@ -129,40 +175,42 @@ impl Context {
                                 Config::default(),
                                 TerminalMode::Mixed,
                             )
-                            .expect("not able to create term logger"),
+                            .ok_or_else(|| {
+                                Error::Config("not able to create term logger".to_string())
+                            })?,
                             WriteLogger::new(
                                 LevelFilter::Info,
                                 Config::default(),
-                                File::create(format!("/tmp/master-{}", uuid))
-                                    .expect("not able to create log file"),
+                                File::create(format!("/tmp/master-{}", uuid))?,
                             ),
                         ]);
-                        let mut host_file =
-                            File::open("hosts.conf").expect("Unable to open the file");
+                        let mut host_file = File::open("hosts.conf")?;
                         let mut hosts = String::new();
-                        host_file
-                            .read_to_string(&mut hosts)
-                            .expect("Unable to read the file");
+                        host_file.read_to_string(&mut hosts)?;
                         //                        println!("{:?}", hosts);
-                        let hosts: Hosts = toml::from_str(&hosts)
-                            .expect("unable to process the hosts.conf file in master");
+                        let hosts: Hosts = toml::from_str(&hosts)?;
                         for address in &hosts.slaves {
                             info!("deploying executor at address {:?}", address);
-                            let path = std::env::current_exe()
-                                .expect("couldn't get executable path")
+                            let path = std::env::current_exe()?
                                 .into_os_string()
                                 .into_string()
-                                .expect("couldn't convert os_string to string");
+                                .map_err(|_| {
+                                    Error::Config(
+                                        "executable path is not valid unicode".to_string(),
+                                    )
+                                })?;
                             //                            let path = path.split(" ").collect::<Vec<_>>();
                             //                            let path = path.join("\\ ");
                             //                            println!("{} {:?} slave", address, path);
                             let address_cli = address
                                 .split('@')
                                 .nth(1)
-                                .expect("format of address is wrong")
+                                .ok_or_else(|| {
+                                    Error::Config(format!("format of address {} is wrong", address))
+                                })?
                                 .to_string();
                             address_map.push((address_cli, port));
-                            let local_dir_root = "/tmp";
+                            let local_dir_root = &env::config.local_dir;
                             let uuid = Uuid::new_v4();
                             let local_dir_uuid = uuid.to_string();
                             let local_dir =
@@ -170,43 +218,39 @@ impl Context {
                             //                            println!("local binary dir {:?}", local_dir);
                             let mkdir_output = Command::new("ssh")
                                 .args(&[address, "mkdir", &local_dir.clone()])
-                                .output()
-                                .expect("ls command failed to start");
+                                .output()?;
                             //                            println!("mkdir output {:?}", mkdir_output);
 
                             let binary_name: Vec<_> = path.split('/').collect();
-                            let binary_name = binary_name
-                                .last()
-                                .expect("some problem with executable path");
+                            let binary_name = binary_name.last().ok_or_else(|| {
+                                Error::Config("some problem with executable path".to_string())
+                            })?;
 
                             let remote_path = format!("{}:{}/{}", address, local_dir, binary_name);
                             //                            println!("remote dir {}", remote_path);
                             //                            println!("local binary path {}", path);
-                            let scp_output = Command::new("scp")
-                                .args(&[&path, &remote_path])
-                                .output()
-                                .expect("ls command failed to start");
+                            let scp_output =
+                                Command::new("scp").args(&[&path, &remote_path]).output()?;
                             let path = format!("{}/{}", local_dir, binary_name);
                             info!("remote path {}", path);
                             Command::new("ssh")
                                 .args(&[address, &path, &"slave".to_string(), &port.to_string()])
-                                .spawn()
-                                .expect("ls command failed to start");
+                                .spawn()?;
                             port += 5000;
                         }
-                        Context {
+                        Ok(Context {
                             next_rdd_id,
                             next_shuffle_id,
                             scheduler: Distributed(DistributedScheduler::new(
-                                4,
                                 20,
                                 true,
                                 Some(address_map.clone()),
                                 10000,
-                            )),
+                            )?),
                             address_map,
                             distributed_master: true,
-                        }
+                            shuffle_cleaner: Arc::default(),
+                        })
                         //TODO handle if master is in another node than from where the program is executed
                         //                        ::std::process::exit(0);
                     }
@@ -216,51 +260,118 @@ impl Context {
                 let uuid = Uuid::new_v4().to_string();
                 let _ = CombinedLogger::init(vec![
                     TermLogger::new(LevelFilter::Info, Config::default(), TerminalMode::Mixed)
-                        .expect("not able to create term logger"),
+                        .ok_or_else(|| {
+                            Error::Config("not able to create term logger".to_string())
+                        })?,
                     WriteLogger::new(
                         LevelFilter::Info,
                         Config::default(),
-                        File::create(format!("/tmp/master-{}", uuid))
-                            .expect("not able to create log file"),
+                        File::create(format!("/tmp/master-{}", uuid))?,
                     ),
                 ]);
                 let scheduler = Local(LocalScheduler::new(num_cpus::get(), 20, true));
-                Context {
+                Ok(Context {
                     next_rdd_id,
                     next_shuffle_id,
                     scheduler,
                     address_map: Vec::new(),
                     distributed_master: false,
-                }
+                    shuffle_cleaner: Arc::default(),
+                })
             }
             _ => {
                 let scheduler = Local(LocalScheduler::new(num_cpus::get(), 20, true));
-                Context {
+                Ok(Context {
                     next_rdd_id,
                     next_shuffle_id,
                     scheduler,
                     address_map: Vec::new(),
                     distributed_master: false,
-                }
+                    shuffle_cleaner: Arc::default(),
+                })
             }
         }
     }
     pub fn drop_executors(self) {
         info!("inside context drop in master {}", self.distributed_master);
-        for (address, port) in self.address_map.clone() {
-            //            while let Err(_) = TcpStream::connect(format!("{}:{}", address, port + 10)) {
-            //                continue;
-            //            }
-            let mut stream = TcpStream::connect(format!("{}:{}", address, port + 10))
-                .expect("couldn't connect to executor");
-            let signal = true;
-            let signal = bincode::serialize(&signal).unwrap();
-            let mut message = ::capnp::message::Builder::new_default();
-            let mut task_data = message.init_root::<serialized_data::Builder>();
-            task_data.set_msg(&signal);
-            serialize_packed::write_message(&mut stream, &message);
+        self.send_to_executors(&ExecutorMessage::Exit);
+        // The local directory is shared by all contexts of the process, so it is only removed along with the last one.
+        if active_contexts.fetch_sub(1, Ordering::SeqCst) == 1 {
+            env::env.shuffle_manager.clean_up();
+        }
+    }
+
+    // Hands the shuffles over to the cleaner thread without waiting for their cleanup.
+    pub(crate) fn queue_shuffle_cleanup(&self, shuffle_ids: Vec<usize>) {
+        let mut shuffle_cleaner = self.shuffle_cleaner.lock();
+        let sender = shuffle_cleaner.get_or_insert_with(|| {
+            let (sender, receiver) = channel::<usize>();
+            // The cleaner gets its own empty queue, so that it does not keep the sender alive and exits once
+            // all copies of this context are dropped.
+            let context = Context {
+                next_rdd_id: self.next_rdd_id.clone(),
+                next_shuffle_id: self.next_shuffle_id.clone(),
+                scheduler: self.scheduler.clone(),
+                address_map: self.address_map.clone(),
+                distributed_master: self.distributed_master,
+                shuffle_cleaner: Arc::default(),
+            };
+            std::thread::spawn(move || {
+                for shuffle_id in receiver {
+                    context.cleanup_shuffle(shuffle_id);
+                }
+            });
+            sender
+        });
+        for shuffle_id in shuffle_ids {
+            // Only fails once the cleaner has exited, which leaves nothing to clean up with.
+            let _ = sender.send(shuffle_id);
+        }
+    }
+
+    // Deletes the outputs of a shuffle on the master and all executors. Called once no rdd depends on the shuffle anymore.
+    pub(crate) fn cleanup_shuffle(&self, shuffle_id: usize) {
+        info!("cleaning up shuffle {}", shuffle_id);
+        self.scheduler.remove_shuffle(shuffle_id);
+        env::env.map_output_tracker.unregister_shuffle(shuffle_id);
+        env::env.shuffle_manager.remove_shuffle(shuffle_id);
+        self.send_to_executors(&ExecutorMessage::RemoveShuffle(shuffle_id));
+    }
+
+    fn send_to_executors(&self, msg: &ExecutorMessage) {
+        let executors = self
+            .address_map
+            .iter()
+            .map(|(address, port)| (&address[..], *port));
+        send_to_executors(executors, msg);
+    }
+    /// Ids of the jobs which are currently running.
+    pub fn active_jobs(&self) -> Vec<usize> {
+        self.scheduler.active_jobs()
+    }
+
+    /// Cancels a running job. The action which started it returns `Error::JobCancelled`.
+    pub fn cancel_job(&self, job_id: usize) {
+        self.scheduler.cancel_job(job_id);
+    }
+
+    pub fn cancel_all_jobs(&self) {
+        for job_id in self.active_jobs() {
+            self.cancel_job(job_id);
         }
     }
+
+    /// Progress of a running job, None if it is not running.
+    pub fn job_progress(&self, job_id: usize) -> Option<JobProgress> {
+        self.scheduler.job_progress(job_id)
+    }
+
+    /// Sets the scheduling pool of the jobs started from the current thread, `None` goes back to the
+    /// default pool. The pools are configured with SPARK_SCHEDULER_MODE and SPARK_SCHEDULER_POOLS.
+    pub fn set_job_pool(&self, pool: Option<&str>) {
+        set_current_pool(pool.map(str::to_string));
+    }
+
     pub fn new_rdd_id(&self) -> usize {
         self.next_rdd_id.fetch_add(1, Ordering::SeqCst)
     }
@@ -279,15 +390,23 @@ impl Context {
         ParallelCollection::new(self.clone(), seq, num_slices)
     }
 
-    pub fn run_job<T: Data, U: Data, RT, F>(&mut self, rdd: Arc<RT>, func: F) -> Vec<U>
+    /// Distributes a collection with one partition per element, which is preferably computed on
+    /// the hosts given along with it, like the hosts storing a file.
+    pub fn make_rdd_with_locations<T: Data>(
+        &self,
+        seq: Vec<(T, Vec<Ipv4Addr>)>,
+    ) -> ParallelCollection<T> {
+        ParallelCollection::with_locations(self.clone(), seq)
+    }
+
+    pub fn run_job<T: Data, U: Data, RT, F>(&mut self, rdd: Arc<RT>, func: F) -> Result<Vec<U>>
     where
         F: SerFunc(Box<dyn Iterator<Item = T>>) -> U,
         RT: Rdd<T> + 'static,
     {
         let cl = Fn!([func] move | (task_context, iter) | (*func)(iter));
-        let func = Arc::new(cl);
-        self.scheduler
-            .run_job(func, rdd.clone(), (0..rdd.number_of_splits()).collect(), false)
+        let partitions = (0..rdd.number_of_splits()).collect();
+        self.run_job_as(new_run_id(), rdd, Arc::new(cl), partitions)
     }
 
     pub fn run_job_on_partitions<T: Data, U: Data, RT, F, P>(
@@ -295,7 +414,7 @@ impl Context {
         rdd: Arc<RT>,
         func: F,
         partitions: P,
-    ) -> Vec<U>
+    ) -> Result<Vec<U>>
     where
         F: Fn(Box<dyn Iterator<Item = T>>) -> U
             + Send
@@ -308,18 +427,65 @@ impl Context {
         P: IntoIterator<Item = usize>,
     {
         let cl = Fn!([func] move | (task_context, iter) | (*func)(iter));
-        self.scheduler
-            .run_job(Arc::new(cl), rdd, partitions.into_iter().collect(), false)
+        let partitions = partitions.into_iter().collect();
+        self.run_job_as(new_run_id(), rdd, Arc::new(cl), partitions)
     }
 
-    pub fn run_job_with_context<T: Data, U: Data, RT, F>(&mut self, rdd: Arc<RT>, func: F) -> Vec<U>
+    pub fn run_job_with_context<T: Data, U: Data, RT, F>(
+        &mut self,
+        rdd: Arc<RT>,
+        func: F,
+    ) -> Result<Vec<U>>
     where
         F: SerFunc((TasKContext, Box<dyn Iterator<Item = T>>)) -> U,
         RT: Rdd<T> + 'static,
     {
         info!("inside run job in context");
-        let func = Arc::new(func);
-        self.scheduler
-            .run_job(func, rdd.clone(), (0..rdd.number_of_splits()).collect(), false)
+        let partitions = (0..rdd.number_of_splits()).collect();
+        self.run_job_as(new_run_id(), rdd, Arc::new(func), partitions)
+    }
+
+    /// Starts a job in the background. The returned handle resolves to the results of all partitions.
+    pub fn submit_job<T: Data, U: Data, RT, F>(&self, rdd: Arc<RT>, func: F) -> JobHandle<Vec<U>>
+    where
+        F: SerFunc(Box<dyn Iterator<Item = T>>) -> U,
+        RT: Rdd<T> + 'static,
+    {
+        self.submit_job_with(rdd, func, |results| results)
+    }
+
+    // Like submit_job, with the results of the partitions combined by `finish` on the driver.
+    pub(crate) fn submit_job_with<T: Data, U: Data, RT, F, R, G>(
+        &self,
+        rdd: Arc<RT>,
+        func: F,
+        finish: G,
+    ) -> JobHandle<R>
+    where
+        F: SerFunc(Box<dyn Iterator<Item = T>>) -> U,
+        RT: Rdd<T> + 'static,
+        R: Send + 'static,
+        G: FnOnce(Vec<U>) -> R + Send + 'static,
+    {
+        let cl = Fn!([func] move | (task_context, iter) | (*func)(iter));
+        let partitions = (0..rdd.number_of_splits()).collect();
+        let func = Arc::new(cl);
+        JobHandle::spawn(self.clone(), move |mut sc, run_id| {
+            sc.run_job_as(run_id, rdd, func, partitions).map(finish)
+        })
+    }
+
+    fn run_job_as<T: Data, U: Data, RT, F>(
+        &mut self,
+        run_id: usize,
+        rdd: Arc<RT>,
+        func: Arc<F>,
+        partitions: Vec<usize>,
+    ) -> Result<Vec<U>>
+    where
+        F: SerFunc((TasKContext, Box<dyn Iterator<Item = T>>)) -> U,
+        RT: Rdd<T> + 'static,
+    {
+        self.scheduler.run_job(run_id, func, rdd, partitions, false)
     }
 }
@@ -1,9 +1,21 @@
 use super::*;
+use parking_lot::{Condvar, Mutex};
 use std::any::Any;
-use std::collections::HashMap;
+use std::collections::btree_map::BTreeMap;
+use std::collections::btree_set::BTreeSet;
+use std::collections::vec_deque::VecDeque;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
+use std::net::Ipv4Addr;
+use std::option::Option;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use threadpool::ThreadPool;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FetchFailedVals {
     pub server_uri: String,
     pub shuffle_id: usize,
@@ -19,25 +31,10 @@ pub struct CompletionEvent {
     pub reason: TastEndReason,
     pub result: Option<Box<dyn Any + Send + Sync>>,
     pub accum_updates: HashMap<i64, Box<dyn Any + Send + Sync>>,
+    // time the task took from taking a slot until its result was back
+    pub duration: Duration,
 }
 
-//impl CompletionEvent {
-//    pub fn get_result<T: 'static + Send + Sync + serde_traitobject::Any + Debug>(&mut self) -> T {
-//        if let Some(data) = self.result.take() {
-//            //            let data = data as Box<Any>;
-//            let res: Box<T> = Box::<Any>::downcast(data.into_any()).unwrap();
-//            *res
-//        //            if let Ok(res) = data.downcast::<T>() {
-//        //                *res
-//        //            } else {
-//        //                panic!("unable to downcast to appropriate type");
-//        //            }
-//        } else {
-//            panic!("result seems to empty");
-//        }
-//    }
-//}
-
 pub enum TastEndReason {
     Success,
     FetchFailed(FetchFailedVals),
@@ -45,23 +42,1303 @@ pub enum TastEndReason {
     OtherFailure(String),
 }
 
-pub trait DAGTask: TaskBase {
-    fn get_run_id(&self) -> usize;
-    fn get_stage_id(&self) -> usize;
-    fn get_gen(&self) -> i64;
-    fn generation(&self) -> Option<i64> {
-        Some(self.get_gen())
+// Finds the rdd of a stage which reads the stage's shuffle input, following single one-to-one dependencies,
+// which keep the number of partitions. Returns the rdd and the id of the shuffle it reads.
+pub(crate) fn shuffle_reader(rdd: Arc<dyn RddBase>) -> Option<(Arc<dyn RddBase>, usize)> {
+    let parent = match rdd.get_dependencies() {
+        [Dependency::ShuffleDependency(dep)] => return Some((rdd.clone(), dep.get_shuffle_id())),
+        [Dependency::OneToOneDependency(dep)] => dep.get_rdd_base(),
+        _ => return None,
+    };
+    shuffle_reader(parent)
+}
+
+// Runs tasks for the DAGScheduler, either in the current process or on remote executors.
+pub trait TaskBackend: Clone + Send + Sync + 'static {
+    /// Executors the tasks run on. The scheduler hands out their slots, one per core.
+    fn executors(&self) -> Vec<ExecutorResources>;
+    /// Runs a task, given along with its serialized form, on the executor with the given index
+    /// and returns its result. Called from the scheduler's thread pool once the executor has a
+    /// free slot for the task, so it blocks until the task is done.
+    fn run_task(&self, executor: usize, task: &TaskOption, task_bytes: Vec<u8>) -> TaskResult;
+    /// Asks the places where tasks run to interrupt the tasks of a cancelled job.
+    fn cancel_job(&self, _run_id: usize) {}
+    /// Whether a speculative copy of a task can run somewhere else than the task itself.
+    fn can_speculate(&self) -> bool {
+        true
     }
 }
 
-pub trait DAGScheduler: Scheduler {
-    fn submit_tasks(&self, tasks: Vec<Box<dyn TaskBase>>, run_id: i64) -> ();
+// Run ids are unique within the process, since cancelled runs are tracked per process and several
+// contexts may run jobs at the same time.
+static next_run_id: AtomicUsize = AtomicUsize::new(0);
+
+pub(crate) fn new_run_id() -> usize {
+    next_run_id.fetch_add(1, Ordering::SeqCst)
+}
+
+// Scheduler core shared by local and distributed mode. It splits jobs into stages at shuffle boundaries,
+// submits the tasks of a stage once its parent stages are available and resubmits stages whose map outputs
+// got lost. Running the tasks themselves is left to the TaskBackend.
+#[derive(Clone)]
+pub struct DAGScheduler<B: TaskBackend> {
+    // one thread per executor slot, running the tasks which got a slot from pools. Only created by
+    // the first dispatch, as every deserialized context builds a scheduler which never runs a job.
+    thread_pool: Arc<Mutex<Option<ThreadPool>>>,
+    pools: Arc<Mutex<SchedulingPools>>,
+    // scheduling pool of each running job
+    job_pools: Arc<Mutex<HashMap<usize, String>>>,
+    // when the first attempt of each running task started on its slot, by run id and task id
+    task_launches: Arc<Mutex<HashMap<(usize, usize), Instant>>>,
+    // None unless stragglers get a speculative copy
+    speculation: Option<SpeculationConfig>,
+    max_failures: usize,
+    resubmit_timeout: u128,
+    poll_timeout: i64,
+    event_queues: Arc<Mutex<HashMap<usize, VecDeque<CompletionEvent>>>>,
+    // notified with event_queues locked whenever an event is queued or a queue is removed
+    event_ready: Arc<Condvar>,
+    job_progress: Arc<Mutex<HashMap<usize, JobProgress>>>,
+    next_task_id: Arc<AtomicUsize>,
+    next_stage_id: Arc<AtomicUsize>,
+    id_to_stage: Arc<Mutex<HashMap<usize, Stage>>>,
+    // map stage id of each shuffle, the stages themselves are only kept in id_to_stage
+    shuffle_to_map_stage: Arc<Mutex<HashMap<usize, usize>>>,
+    cache_locs: Arc<Mutex<HashMap<usize, Vec<Vec<Ipv4Addr>>>>>,
+    master: bool,
+    map_output_tracker: MapOutputTracker,
+    backend: B,
+}
+
+impl<B: TaskBackend> DAGScheduler<B> {
+    pub fn new(max_failures: usize, master: bool, backend: B) -> Self {
+        let pools = SchedulingPools::new(
+            env::config.scheduler_mode,
+            env::config.scheduler_pools.clone(),
+            LocalityWaits::from_config(),
+            backend.executors(),
+        );
+        DAGScheduler {
+            thread_pool: Arc::new(Mutex::new(None)),
+            pools: Arc::new(Mutex::new(pools)),
+            job_pools: Arc::new(Mutex::new(HashMap::new())),
+            task_launches: Arc::new(Mutex::new(HashMap::new())),
+            speculation: SpeculationConfig::from_config(),
+            max_failures,
+            resubmit_timeout: 2000,
+            poll_timeout: 500,
+            event_queues: Arc::new(Mutex::new(HashMap::new())),
+            event_ready: Arc::new(Condvar::new()),
+            job_progress: Arc::new(Mutex::new(HashMap::new())),
+            next_task_id: Arc::new(AtomicUsize::new(0)),
+            next_stage_id: Arc::new(AtomicUsize::new(0)),
+            id_to_stage: Arc::new(Mutex::new(HashMap::new())),
+            shuffle_to_map_stage: Arc::new(Mutex::new(HashMap::new())),
+            cache_locs: Arc::new(Mutex::new(HashMap::new())),
+            master,
+            map_output_tracker: env::env.map_output_tracker.clone(),
+            backend,
+        }
+    }
+
+    fn get_cache_locs(&self, rdd: Arc<dyn RddBase>) -> Option<Vec<Vec<Ipv4Addr>>> {
+        let cache_locs = self.cache_locs.lock();
+        let locs_opt = cache_locs.get(&rdd.get_rdd_id());
+        match locs_opt {
+            Some(locs) => Some(locs.clone()),
+            None => None,
+        }
+        //        (self.cache_locs.lock().get(&rdd.get_rdd_id())).clone()
+    }
+
+    fn update_cache_locs(&self) {
+        let mut locs = self.cache_locs.lock();
+        *locs = env::env.cache_tracker.get_location_snapshot();
+    }
+
     fn task_ended(
+        event_queues: Arc<Mutex<HashMap<usize, VecDeque<CompletionEvent>>>>,
+        event_ready: Arc<Condvar>,
         task: Box<dyn TaskBase>,
         reason: TastEndReason,
-        result: Box<dyn Any>,
-        accum_updates: HashMap<i64, Box<dyn Any>>,
+        result: Box<dyn Any + Send + Sync>,
+        duration: Duration,
+        //TODO accumvalues needs to be done
     ) {
-        unimplemented!()
+        let result = Some(result);
+        if let Some(queue) = event_queues.lock().get_mut(&(task.get_run_id())) {
+            queue.push_back(CompletionEvent {
+                task,
+                reason,
+                //                    result: Some(Box::new(result)),
+                result,
+                accum_updates: HashMap::new(),
+                duration,
+            });
+            event_ready.notify_all();
+        } else {
+            info!("ignoring completion event for DAG Job");
+        }
+    }
+
+    /// Cancels a running job. Its tasks which did not start yet are not run anymore and the running ones
+    /// are told to stop, and the job returns `Error::JobCancelled`.
+    pub fn cancel_job(&self, run_id: usize) {
+        info!("cancelling job {}", run_id);
+        cancel_job_locally(run_id);
+        self.event_queues.lock().remove(&run_id);
+        self.event_ready.notify_all();
+        self.backend.cancel_job(run_id);
+    }
+
+    fn update_progress(&self, run_id: usize, finished_tasks: usize, total_tasks: usize) {
+        self.job_progress.lock().insert(
+            run_id,
+            JobProgress {
+                finished_tasks,
+                total_tasks,
+            },
+        );
+    }
+
+    fn end_job(&self, run_id: usize) {
+        self.event_queues.lock().remove(&run_id);
+        self.job_progress.lock().remove(&run_id);
+        self.job_pools.lock().remove(&run_id);
+        self.task_launches
+            .lock()
+            .retain(|(run, _), _| *run != run_id);
+    }
+
+    /// Progress of a running job, None if it is not running.
+    pub fn job_progress(&self, run_id: usize) -> Option<JobProgress> {
+        self.job_progress.lock().get(&run_id).cloned()
+    }
+
+    /// Ids of the jobs which are currently running.
+    pub fn active_jobs(&self) -> Vec<usize> {
+        let mut jobs = self.event_queues.lock().keys().cloned().collect::<Vec<_>>();
+        jobs.sort();
+        jobs
+    }
+
+    // Forgets the map stage of a shuffle which is not referenced by any rdd anymore.
+    pub fn remove_shuffle(&self, shuffle_id: usize) {
+        let stage_id = self.shuffle_to_map_stage.lock().remove(&shuffle_id);
+        if let Some(stage_id) = stage_id {
+            self.id_to_stage.lock().remove(&stage_id);
+        }
+    }
+
+    fn get_shuffle_map_stage(&self, shuf: Arc<dyn ShuffleDependencyTrait>) -> Stage {
+        info!("inside get_shufflemap stage");
+        //        let log_output = format!("inside get_shufflemap stage");
+        //        env::log_file.lock().write(&log_output.as_bytes());
+        let stage_id = self
+            .shuffle_to_map_stage
+            .lock()
+            .get(&shuf.get_shuffle_id())
+            .cloned();
+        match stage_id {
+            Some(stage_id) => self.id_to_stage.lock()[&stage_id].clone(),
+            None => {
+                info!("inside get_shufflemap stage before");
+                let stage = self.new_stage(shuf.get_rdd_base(), Some(shuf.clone()));
+                self.shuffle_to_map_stage
+                    .lock()
+                    .insert(shuf.get_shuffle_id(), stage.id);
+                info!("inside get_shufflemap return");
+                stage
+            }
+        }
+    }
+
+    fn new_stage(
+        &self,
+        rdd_base: Arc<dyn RddBase>,
+        shuffle_dependency: Option<Arc<dyn ShuffleDependencyTrait>>,
+    ) -> Stage {
+        info!("inside new stage");
+        env::env
+            .cache_tracker
+            .register_rdd(rdd_base.get_rdd_id(), rdd_base.number_of_splits());
+        if !shuffle_dependency.is_none() {
+            info!("shuffle dependcy and registering mapoutput tracker");
+            self.map_output_tracker.register_shuffle(
+                shuffle_dependency.clone().unwrap().get_shuffle_id(),
+                rdd_base.number_of_splits(),
+            );
+            info!("new stage tracker after");
+        }
+        let id = self.next_stage_id.fetch_add(1, Ordering::SeqCst);
+        info!("new stage id {}", id);
+        let stage = Stage::new(
+            id,
+            rdd_base.clone(),
+            shuffle_dependency,
+            self.get_parent_stages(rdd_base),
+        );
+        self.id_to_stage.lock().insert(id, stage.clone());
+        info!("new stage stage return");
+        stage
+    }
+
+    fn visit_for_parent_stages(
+        &self,
+        parents: &mut BTreeSet<Stage>,
+        visited: &mut BTreeSet<Arc<dyn RddBase>>,
+        rdd: Arc<dyn RddBase>,
+    ) {
+        info!(
+            "parent stages {:?}",
+            parents.iter().map(|x| x.id).collect::<Vec<_>>()
+        );
+        info!(
+            "visisted stages {:?}",
+            visited.iter().map(|x| x.get_rdd_id()).collect::<Vec<_>>()
+        );
+        if !visited.contains(&rdd) {
+            visited.insert(rdd.clone());
+            env::env
+                .cache_tracker
+                .register_rdd(rdd.get_rdd_id(), rdd.number_of_splits());
+            for dep in rdd.get_dependencies() {
+                match dep {
+                    Dependency::ShuffleDependency(shuf_dep) => {
+                        parents.insert(self.get_shuffle_map_stage(shuf_dep.clone()));
+                    }
+                    Dependency::OneToOneDependency(oto_dep) => {
+                        self.visit_for_parent_stages(parents, visited, oto_dep.get_rdd_base())
+                    }
+                    Dependency::NarrowDependency(nar_dep) => {
+                        self.visit_for_parent_stages(parents, visited, nar_dep.get_rdd_base())
+                    } //TODO finish range dependency
+                }
+            }
+        }
+    }
+
+    fn get_parent_stages(&self, rdd: Arc<dyn RddBase>) -> Vec<Stage> {
+        info!("inside get parent stages");
+        let mut parents: BTreeSet<Stage> = BTreeSet::new();
+        let mut visited: BTreeSet<Arc<dyn RddBase>> = BTreeSet::new();
+        self.visit_for_parent_stages(&mut parents, &mut visited, rdd.clone());
+        info!(
+            "parent stages {:?}",
+            parents.iter().map(|x| x.id).collect::<Vec<_>>()
+        );
+        parents.into_iter().collect()
+    }
+
+    fn visit_for_missing_parent_stages(
+        &self,
+        missing: &mut BTreeSet<Stage>,
+        visited: &mut BTreeSet<Arc<dyn RddBase>>,
+        rdd: Arc<dyn RddBase>,
+    ) {
+        info!(
+            "missing stages {:?}",
+            missing.iter().map(|x| x.id).collect::<Vec<_>>()
+        );
+        info!(
+            "visisted stages {:?}",
+            visited.iter().map(|x| x.get_rdd_id()).collect::<Vec<_>>()
+        );
+        if !visited.contains(&rdd) {
+            visited.insert(rdd.clone());
+            // TODO CacheTracker register
+            let locs = self.get_cache_locs(rdd.clone());
+            info!("cache locs {:?}", locs);
+            // the dependencies are only needed when some partition of the rdd is not cached
+            let cached = (0..rdd.number_of_splits()).all(|p| {
+                locs.as_ref()
+                    .and_then(|locs| locs.get(p))
+                    .map_or(false, |locs| !locs.is_empty())
+            });
+            if !cached {
+                for dep in rdd.get_dependencies() {
+                    info!("for dep in missing stages ");
+                    match dep {
+                        Dependency::ShuffleDependency(shuf_dep) => {
+                            let stage = self.get_shuffle_map_stage(shuf_dep.clone());
+                            info!("shuffle stage in missing stages {:?}", stage.id);
+                            if !stage.is_available() {
+                                info!("inserting shuffle stage in missing stages {:?}", stage.id);
+                                missing.insert(stage);
+                            }
+                        }
+                        Dependency::NarrowDependency(nar_dep) => {
+                            info!("narrow stage in missing stages ");
+                            self.visit_for_missing_parent_stages(
+                                missing,
+                                visited,
+                                nar_dep.get_rdd_base(),
+                            )
+                        }
+                        Dependency::OneToOneDependency(one_dep) => {
+                            info!("one to one stage in missing stages ");
+                            self.visit_for_missing_parent_stages(
+                                missing,
+                                visited,
+                                one_dep.get_rdd_base(),
+                            )
+                        } //TODO finish range dependency
+                    }
+                }
+            }
+        }
+    }
+
+    fn get_missing_parent_stages(&self, stage: Stage) -> Vec<Stage> {
+        info!("inside get missing parent stages");
+        let mut missing: BTreeSet<Stage> = BTreeSet::new();
+        let mut visited: BTreeSet<Arc<dyn RddBase>> = BTreeSet::new();
+        self.visit_for_missing_parent_stages(&mut missing, &mut visited, stage.get_rdd());
+        missing.into_iter().collect()
+    }
+
+    pub fn run_job<T: Data, U: Data, F, RT>(
+        &mut self,
+        run_id: usize,
+        func: Arc<F>,
+        final_rdd: Arc<RT>,
+        partitions: Vec<usize>,
+        allow_local: bool,
+    ) -> crate::Result<Vec<U>>
+    where
+        F: SerFunc((TasKContext, Box<dyn Iterator<Item = T>>)) -> U,
+        RT: Rdd<T> + 'static,
+    {
+        info!(
+            "shuffle maanger in final rdd of run job {:?}",
+            env::env.shuffle_manager
+        );
+        let pool = current_pool().unwrap_or_else(|| default_pool.to_string());
+        self.job_pools.lock().insert(run_id, pool);
+        let output_parts = partitions;
+        let num_output_parts = output_parts.len();
+        let final_stage = self.new_stage(final_rdd.clone(), None);
+        let mut results: Vec<Option<U>> = (0..num_output_parts).map(|_| None).collect();
+        let mut finished: Vec<bool> = (0..num_output_parts).map(|_| false).collect();
+        let mut num_finished = 0;
+        let mut waiting: BTreeSet<Stage> = BTreeSet::new();
+        let mut running: BTreeSet<Stage> = BTreeSet::new();
+        let mut failed: BTreeSet<Stage> = BTreeSet::new();
+        let mut pending_tasks: BTreeMap<Stage, BTreeSet<Box<dyn TaskBase>>> = BTreeMap::new();
+        let mut last_fetch_failure_time = 0;
+        // failed attempts of each task, by task id
+        let mut task_failures: HashMap<usize, usize> = HashMap::new();
+        // run times of the finished tasks, and the tasks which already got a speculative copy
+        let mut durations = TaskDurations::default();
+        let mut speculated: HashSet<usize> = HashSet::new();
+
+        //TODO update cache
+        //TODO logging
+
+        if allow_local && final_stage.parents.is_empty() && (num_output_parts == 1) {
+            let split = (final_rdd.splits()[output_parts[0]]).clone();
+            let task_context = TasKContext::new(final_stage.id, output_parts[0], 0, run_id);
+            self.id_to_stage.lock().remove(&final_stage.id);
+            return Ok(vec![func((task_context, final_rdd.iterator(split)))]);
+        }
+
+        self.event_queues.lock().insert(run_id, VecDeque::new());
+
+        self.update_progress(run_id, num_finished, num_output_parts);
+        self.submit_stage(
+            final_stage.clone(),
+            &mut waiting,
+            &mut running,
+            &mut finished,
+            &mut pending_tasks,
+            output_parts.clone(),
+            num_output_parts,
+            final_stage.clone(),
+            func.clone(),
+            final_rdd.clone(),
+            run_id,
+        );
+        info!(
+            "pending stages and tasks {:?}",
+            pending_tasks
+                .iter()
+                .map(|(k, v)| (k.id, v.iter().map(|x| x.get_task_id()).collect::<Vec<_>>()))
+                .collect::<Vec<_>>()
+        );
+
+        while num_finished != num_output_parts {
+            if is_job_cancelled(run_id) {
+                self.end_job(run_id);
+                self.id_to_stage.lock().remove(&final_stage.id);
+                return Err(crate::Error::JobCancelled(run_id));
+            }
+            // tasks waiting for a local slot may accept any slot by now
+            self.dispatch();
+            let event_option = self.wait_for_event(run_id, self.poll_timeout);
+            let time = SystemTime::now();
+            let time = time.duration_since(UNIX_EPOCH).unwrap().as_millis();
+
+            if let Some(mut evt) = event_option {
+                info!("event starting");
+                let stage = self.id_to_stage.lock()[&evt.task.get_stage_id()].clone();
+                info!(
+                    "removing stage task from pending tasks {} {}",
+                    stage.id,
+                    evt.task.get_task_id()
+                );
+                pending_tasks.get_mut(&stage).unwrap().remove(&evt.task);
+                let task_id = evt.task.get_task_id();
+                self.task_launches.lock().remove(&(run_id, task_id));
+                // with speculation a partition can end twice, only its first result counts
+                let partition_done = match evt.task.downcast_ref::<ResultTask<T, U, RT, F>>() {
+                    Some(rt) => finished[rt.output_id],
+                    None => evt
+                        .task
+                        .downcast_ref::<ShuffleMapTask>()
+                        .map_or(false, |smt| !stage.output_locs[smt.partition].is_empty()),
+                };
+                use super::dag_scheduler::TastEndReason::*;
+                match evt.reason {
+                    Success => {
+                        //                        println!("inside run job and inside event success");
+                        //TODO logging
+                        //TODO add to Accumulator
+
+                        // ResultTask alone done now.
+                        let mut result_type = false;
+                        if let Some(_) = evt.task.downcast_ref::<ResultTask<T, U, RT, F>>() {
+                            result_type = true;
+                        }
+                        if result_type {
+                            if let Ok(rt) = evt.task.downcast::<ResultTask<T, U, RT, F>>() {
+                                let result = evt
+                                    .result
+                                    .take()
+                                    .unwrap()
+                                    .downcast_ref::<U>()
+                                    .unwrap()
+                                    .clone();
+                                // a partition can complete twice when it was resubmitted while still running
+                                if !finished[rt.output_id] {
+                                    durations.record(stage.id, evt.duration);
+                                    results[rt.output_id] = Some(result);
+                                    finished[rt.output_id] = true;
+                                    num_finished += 1;
+                                    self.update_progress(run_id, num_finished, num_output_parts);
+                                }
+                            }
+                        } else if let Ok(smt) = evt.task.downcast::<ShuffleMapTask>() {
+                            let result = evt
+                                .result
+                                .take()
+                                .unwrap()
+                                .downcast_ref::<MapStatus>()
+                                .unwrap()
+                                .clone();
+                            info!("result inside queue {:?}", result);
+                            if partition_done {
+                                info!("ignoring duplicate output of map task {}", task_id);
+                            } else {
+                                durations.record(stage.id, evt.duration);
+                                self.id_to_stage
+                                    .lock()
+                                    .get_mut(&smt.stage_id)
+                                    .unwrap()
+                                    .add_output_loc(smt.partition, result);
+                            }
+                            let stage = self.id_to_stage.lock().clone()[&smt.stage_id].clone();
+                            info!(
+                                "pending stages {:?}",
+                                pending_tasks
+                                    .iter()
+                                    .map(|(x, y)| (
+                                        x.id,
+                                        y.iter().map(|k| k.get_task_id()).collect::<Vec<_>>()
+                                    ))
+                                    .collect::<Vec<_>>()
+                            );
+                            info!(
+                                "pending tasks {:?}",
+                                pending_tasks
+                                    .get(&stage)
+                                    .unwrap()
+                                    .iter()
+                                    .map(|x| x.get_task_id())
+                                    .collect::<Vec<_>>()
+                            );
+                            info!(
+                                "running {:?}",
+                                running.iter().map(|x| x.id).collect::<Vec<_>>()
+                            );
+                            info!(
+                                "waiting {:?}",
+                                waiting.iter().map(|x| x.id).collect::<Vec<_>>()
+                            );
+
+                            if running.contains(&stage)
+                                && pending_tasks.get(&stage).unwrap().is_empty()
+                            {
+                                info!("here before registering map outputs ");
+                                //TODO logging
+                                running.remove(&stage);
+                                if !stage.shuffle_dependency.is_none() {
+                                    info!(
+                                        "stage output locs before register mapoutput tracker {:?}",
+                                        stage.output_locs
+                                    );
+                                    let locs = stage
+                                        .output_locs
+                                        .iter()
+                                        .map(|x| match x.get(0) {
+                                            Some(s) => Some(s.to_owned()),
+                                            None => None,
+                                        })
+                                        .collect();
+                                    info!(
+                                        "locs for shuffle id {:?} {:?}",
+                                        stage.clone().shuffle_dependency.unwrap().get_shuffle_id(),
+                                        locs
+                                    );
+                                    let shuffle_id =
+                                        stage.shuffle_dependency.unwrap().get_shuffle_id();
+                                    self.map_output_tracker
+                                        .register_map_outputs(shuffle_id, locs);
+                                    let statistics =
+                                        self.map_output_tracker.get_statistics(shuffle_id);
+                                    info!(
+                                        "shuffle {} wrote {} bytes in {} records",
+                                        shuffle_id,
+                                        statistics.bytes_by_partition.iter().sum::<u64>(),
+                                        statistics.records_by_partition.iter().sum::<u64>()
+                                    );
+                                    info!("here after registering map outputs ");
+                                }
+                                //TODO Cache
+                                self.update_cache_locs();
+                                let mut newly_runnable = Vec::new();
+                                for stage in &waiting {
+                                    info!(
+                                        "waiting stage parent stages for stage {} are {:?}",
+                                        stage.id,
+                                        self.get_missing_parent_stages(stage.clone())
+                                            .iter()
+                                            .map(|x| x.id)
+                                            .collect::<Vec<_>>()
+                                    );
+                                    if self.get_missing_parent_stages(stage.clone()).is_empty() {
+                                        newly_runnable.push(stage.clone())
+                                    }
+                                }
+                                for stage in &newly_runnable {
+                                    waiting.remove(stage);
+                                }
+                                for stage in &newly_runnable {
+                                    running.insert(stage.clone());
+                                }
+                                for stage in newly_runnable {
+                                    self.submit_missing_tasks(
+                                        stage,
+                                        &mut finished,
+                                        &mut pending_tasks,
+                                        output_parts.clone(),
+                                        num_output_parts,
+                                        final_stage.clone(),
+                                        func.clone(),
+                                        final_rdd.clone(),
+                                        run_id,
+                                    );
+                                }
+                            }
+                        }
+                    }
+                    FetchFailed(FetchFailedVals {
+                        server_uri,
+                        shuffle_id,
+                        map_id,
+                        reduce_id,
+                    }) => {
+                        info!(
+                            "fetch of map output {} of shuffle {} from {} failed for reduce {}",
+                            map_id, shuffle_id, server_uri, reduce_id
+                        );
+                        let failed_stage = self
+                            .id_to_stage
+                            .lock()
+                            .get(&evt.task.get_stage_id())
+                            .unwrap()
+                            .clone();
+                        running.remove(&failed_stage);
+                        failed.insert(failed_stage);
+                        // the map output is lost, so the map stage has to be resubmitted before the failed stage
+                        let map_stage_id =
+                            self.shuffle_to_map_stage.lock().get(&shuffle_id).cloned();
+                        if let Some(map_stage_id) = map_stage_id {
+                            let mut id_to_stage = self.id_to_stage.lock();
+                            let map_stage = id_to_stage.get_mut(&map_stage_id).unwrap();
+                            map_stage.remove_output_loc(map_id, server_uri.clone());
+                            failed.insert(map_stage.clone());
+                        }
+                        self.map_output_tracker.unregister_map_output(
+                            shuffle_id,
+                            map_id,
+                            server_uri.clone(),
+                        );
+                        last_fetch_failure_time = time;
+                    }
+                    TastEndReason::Error(_) | OtherFailure(_) if partition_done => {
+                        info!(
+                            "ignoring failure of task {}, its partition is done",
+                            task_id
+                        );
+                    }
+                    TastEndReason::Error(_) | OtherFailure(_) => {
+                        speculated.remove(&task_id);
+                        let cause = match &evt.reason {
+                            TastEndReason::Error(e) => e.to_string(),
+                            OtherFailure(msg) => msg.clone(),
+                            _ => unreachable!(),
+                        };
+                        let failures = task_failures.entry(evt.task.get_task_id()).or_insert(0);
+                        *failures += 1;
+                        error!(
+                            "task {} of stage {} failed (attempt {}): {}",
+                            evt.task.get_task_id(),
+                            stage.id,
+                            *failures,
+                            cause
+                        );
+                        if *failures >= self.max_failures {
+                            // the other tasks of the job are of no use anymore
+                            self.cancel_job(run_id);
+                            self.end_job(run_id);
+                            self.id_to_stage.lock().remove(&final_stage.id);
+                            return Err(crate::Error::TaskFailed {
+                                task_id: evt.task.get_task_id(),
+                                stage_id: stage.id,
+                                failures: *failures,
+                                cause,
+                            });
+                        }
+                        // runs the task again as its next attempt
+                        let attempt_id = *failures;
+                        let task = match evt.task.downcast::<ResultTask<T, U, RT, F>>() {
+                            Ok(mut rt) => {
+                                rt.attempt_id = attempt_id;
+                                TaskOption::ResultTask(rt)
+                            }
+                            Err(task) => match task.downcast::<ShuffleMapTask>() {
+                                Ok(mut smt) => {
+                                    smt.attempt_id = attempt_id;
+                                    TaskOption::ShuffleMapTask(smt)
+                                }
+                                Err(_) => panic!("task of unknown type failed"),
+                            },
+                        };
+                        let pending_task: Box<dyn TaskBase> = match &task {
+                            TaskOption::ResultTask(tsk) => Box::new(
+                                tsk.downcast_ref::<ResultTask<T, U, RT, F>>()
+                                    .unwrap()
+                                    .clone(),
+                            ),
+                            TaskOption::ShuffleMapTask(tsk) => {
+                                Box::new(tsk.downcast_ref::<ShuffleMapTask>().unwrap().clone())
+                            }
+                        };
+                        pending_tasks.get_mut(&stage).unwrap().insert(pending_task);
+                        self.submit_task::<T, U, RT, F>(task, 0);
+                    }
+                }
+            }
+            if !failed.is_empty() && (time > (last_fetch_failure_time + self.resubmit_timeout)) {
+                self.update_cache_locs();
+                for stage in &failed {
+                    self.submit_stage(
+                        stage.clone(),
+                        &mut waiting,
+                        &mut running,
+                        &mut finished,
+                        &mut pending_tasks,
+                        output_parts.clone(),
+                        num_output_parts,
+                        final_stage.clone(),
+                        func.clone(),
+                        final_rdd.clone(),
+                        run_id,
+                    );
+                }
+                failed.clear();
+            }
+            if let Some(config) = self.speculation.filter(|_| self.backend.can_speculate()) {
+                self.speculate::<T, U, RT, F>(
+                    run_id,
+                    &pending_tasks,
+                    &durations,
+                    &mut speculated,
+                    config,
+                );
+            }
+        }
+
+        self.end_job(run_id);
+        // The final stage is not reused by later jobs. Keeping it would keep the final rdd, and the shuffles it depends on, alive.
+        self.id_to_stage.lock().remove(&final_stage.id);
+        //        let dur = time::Duration::from_millis(20000);
+        //        thread::sleep(dur);
+        results
+            .into_iter()
+            .map(|s| match s {
+                Some(v) => Ok(v),
+                None => Err(crate::Error::JobAborted(
+                    "some results still missing".to_string(),
+                )),
+            })
+            .collect()
+    }
+
+    // Merges small reduce partitions of a stage into ranges once the sizes of its shuffle input are known.
+    // Returns the updated stage if it was coalesced, see SPARK_SHUFFLE_COALESCE_PARTITIONS.
+    fn coalesce_stage_partitions(&self, stage: &Stage) -> Option<Stage> {
+        if !env::config.shuffle_coalesce_partitions
+            || stage.num_available_outputs > 0
+            || !self.get_missing_parent_stages(stage.clone()).is_empty()
+        {
+            return None;
+        }
+        let (rdd, shuffle_id) = shuffle_reader(stage.get_rdd())?;
+        let statistics = self.map_output_tracker.get_statistics(shuffle_id);
+        if statistics.bytes_by_partition.len() != stage.num_partitions {
+            return None;
+        }
+        let ranges = coalesce_partitions(
+            &statistics.bytes_by_partition,
+            env::config.shuffle_target_partition_size,
+        );
+        if ranges.len() == stage.num_partitions || rdd.coalesced_split(0, 0, 1).is_none() {
+            return None;
+        }
+        let mut id_to_stage = self.id_to_stage.lock();
+        let stage = id_to_stage.get_mut(&stage.id).unwrap();
+        info!(
+            "coalesced {} partitions of stage {} into {:?}",
+            stage.num_partitions, stage.id, ranges
+        );
+        stage.num_partitions = ranges.len();
+        stage.output_locs = vec![Vec::new(); stage.num_partitions];
+        stage.coalesced = Some(ranges);
+        Some(stage.clone())
+    }
+
+    fn submit_stage<T: Data, U: Data, F, RT>(
+        &self,
+        stage: Stage,
+        waiting: &mut BTreeSet<Stage>,
+        running: &mut BTreeSet<Stage>,
+        finished: &mut Vec<bool>,
+        pending_tasks: &mut BTreeMap<Stage, BTreeSet<Box<dyn TaskBase>>>,
+        output_parts: Vec<usize>,
+        num_output_parts: usize,
+        final_stage: Stage,
+        func: Arc<F>,
+        final_rdd: Arc<RT>,
+        run_id: usize,
+    ) where
+        F: SerFunc((TasKContext, Box<dyn Iterator<Item = T>>)) -> U,
+        RT: Rdd<T> + 'static,
+    {
+        info!("submiting stage {}", stage.id);
+        if !waiting.contains(&stage) && !running.contains(&stage) {
+            let missing = self.get_missing_parent_stages(stage.clone());
+            info!(
+                "inside submit stage missing stages {:?}",
+                missing.iter().map(|x| x.id).collect::<Vec<_>>()
+            );
+            if missing.is_empty() {
+                self.submit_missing_tasks(
+                    stage.clone(),
+                    finished,
+                    pending_tasks,
+                    output_parts.clone(),
+                    num_output_parts,
+                    final_stage.clone(),
+                    func,
+                    final_rdd.clone(),
+                    run_id,
+                );
+                running.insert(stage.clone());
+            } else {
+                for parent in missing {
+                    self.submit_stage(
+                        parent,
+                        waiting,
+                        running,
+                        finished,
+                        pending_tasks,
+                        output_parts.clone(),
+                        num_output_parts,
+                        final_stage.clone(),
+                        func.clone(),
+                        final_rdd.clone(),
+                        run_id,
+                    );
+                }
+                waiting.insert(stage.clone());
+            }
+        }
+    }
+
+    fn submit_missing_tasks<T: Data, U: Data, F, RT>(
+        &self,
+        stage: Stage,
+        finished: &mut Vec<bool>,
+        pending_tasks: &mut BTreeMap<Stage, BTreeSet<Box<dyn TaskBase>>>,
+        output_parts: Vec<usize>,
+        num_output_parts: usize,
+        final_stage: Stage,
+        func: Arc<F>,
+        final_rdd: Arc<RT>,
+        run_id: usize,
+    ) where
+        F: SerFunc((TasKContext, Box<dyn Iterator<Item = T>>)) -> U,
+        RT: Rdd<T> + 'static,
+    {
+        // the stage may be an older copy, e.g. from the failed set, whose output locations are outdated
+        let mut stage = self.id_to_stage.lock()[&stage.id].clone();
+        if stage != final_stage {
+            if let Some(coalesced) = self.coalesce_stage_partitions(&stage) {
+                stage = coalesced;
+            }
+        }
+        let my_pending = pending_tasks
+            .entry(stage.clone())
+            .or_insert_with(BTreeSet::new);
+        if stage == final_stage {
+            info!("final stage {}", stage.id);
+            let mut id_in_job = 0;
+            for id in 0..num_output_parts {
+                // resubmitted after a fetch failure, only the unfinished partitions have to run again
+                if finished[id] {
+                    continue;
+                }
+                let part = output_parts[id];
+                let locs = self.get_preferred_locs(final_rdd.clone() as Arc<dyn RddBase>, part);
+                let result_task = ResultTask::new(
+                    self.next_task_id.fetch_add(1, Ordering::SeqCst),
+                    run_id,
+                    final_stage.id,
+                    final_rdd.clone(),
+                    func.clone(),
+                    part,
+                    stage.split(part),
+                    locs,
+                    id,
+                );
+                my_pending.insert(Box::new(result_task.clone()));
+                self.submit_task::<T, U, RT, F>(
+                    TaskOption::ResultTask(Box::new(result_task)),
+                    id_in_job,
+                );
+                id_in_job += 1;
+            }
+        } else {
+            let mut id_in_job = 0;
+            for p in 0..stage.num_partitions {
+                info!("shuffle_stage {}", stage.id);
+                if stage.output_locs[p].is_empty() {
+                    let locs = self.get_preferred_locs(stage.get_rdd(), p);
+                    info!("creating task for {} partition  {}", stage.id, p);
+                    let shuffle_map_task = ShuffleMapTask::new(
+                        self.next_task_id.fetch_add(1, Ordering::SeqCst),
+                        run_id,
+                        stage.id,
+                        stage.rdd.clone(),
+                        stage.shuffle_dependency.clone().unwrap(),
+                        p,
+                        stage.split(p),
+                        locs,
+                    );
+                    info!(
+                        "creating task for {} partition  {} and shuffle id {}",
+                        stage.id,
+                        p,
+                        shuffle_map_task.dep.get_shuffle_id()
+                    );
+                    my_pending.insert(Box::new(shuffle_map_task.clone()));
+                    self.submit_task::<T, U, RT, F>(
+                        TaskOption::ShuffleMapTask(Box::new(shuffle_map_task)),
+                        id_in_job,
+                    );
+                    id_in_job += 1;
+                }
+            }
+        }
+    }
+
+    // Executors caching the partition come first, then the hosts storing its input, like file
+    // splits. Otherwise the partition inherits the locations of the first narrow parent which has
+    // any.
+    fn get_preferred_locs(&self, rdd: Arc<dyn RddBase>, partition: usize) -> Vec<TaskLocation> {
+        if let Some(cached) = self.get_cache_locs(rdd.clone()) {
+            if let Some(cached) = cached.get(partition) {
+                if !cached.is_empty() {
+                    return cached.iter().cloned().map(TaskLocation::Executor).collect();
+                }
+            }
+        }
+        let rdd_prefs = rdd.preferred_locations(rdd.splits()[partition].clone());
+        if !rdd_prefs.is_empty() {
+            return rdd_prefs.into_iter().map(TaskLocation::Host).collect();
+        }
+        for dep in rdd.get_dependencies().iter() {
+            let locs = match dep {
+                Dependency::OneToOneDependency(oto_dep) => {
+                    self.get_preferred_locs(oto_dep.get_rdd_base(), partition)
+                }
+                Dependency::NarrowDependency(nar_dep) => nar_dep
+                    .get_parents(partition)
+                    .into_iter()
+                    .map(|in_part| self.get_preferred_locs(nar_dep.get_rdd_base(), in_part))
+                    .find(|locs| !locs.is_empty())
+                    .unwrap_or_default(),
+                Dependency::ShuffleDependency(_) => Vec::new(),
+            };
+            if !locs.is_empty() {
+                return locs;
+            }
+        }
+        Vec::new()
+    }
+
+    // Launches a copy of the tasks which run much longer than the finished tasks of their stage.
+    // Whichever copy ends first provides the result of the partition, the other one is ignored.
+    fn speculate<T: Data, U: Data, RT, F>(
+        &self,
+        run_id: usize,
+        pending_tasks: &BTreeMap<Stage, BTreeSet<Box<dyn TaskBase>>>,
+        durations: &TaskDurations,
+        speculated: &mut HashSet<usize>,
+        config: SpeculationConfig,
+    ) where
+        F: SerFunc((TasKContext, Box<dyn Iterator<Item = T>>)) -> U,
+        RT: Rdd<T> + 'static,
+    {
+        let launches = self.task_launches.lock().clone();
+        for (stage, tasks) in pending_tasks {
+            let total_tasks = durations.finished(stage.id) + tasks.len();
+            let threshold = durations.threshold(stage.id, total_tasks, config);
+            let threshold = match threshold {
+                Some(threshold) => threshold,
+                None => continue,
+            };
+            for task in tasks {
+                let task_id = task.get_task_id();
+                // tasks still waiting for a slot are not stragglers
+                let running_for = match launches.get(&(run_id, task_id)) {
+                    Some(launched) => launched.elapsed(),
+                    None => continue,
+                };
+                if running_for <= threshold || speculated.contains(&task_id) {
+                    continue;
+                }
+                let copy = if let Some(rt) = task.downcast_ref::<ResultTask<T, U, RT, F>>() {
+                    TaskOption::ResultTask(Box::new(rt.clone()))
+                } else if let Some(smt) = task.downcast_ref::<ShuffleMapTask>() {
+                    TaskOption::ShuffleMapTask(Box::new(smt.clone()))
+                } else {
+                    continue;
+                };
+                info!(
+                    "task {} of stage {} runs for {:?}, longer than {:?}, speculating it",
+                    task_id, stage.id, running_for, threshold
+                );
+                speculated.insert(task_id);
+                self.submit_task::<T, U, RT, F>(copy, 0);
+            }
+        }
+    }
+
+    // Waits at most `timeout` milliseconds for the next event of the job, waking up as soon as a task ends.
+    fn wait_for_event(&mut self, run_id: usize, timeout: i64) -> Option<CompletionEvent> {
+        let deadline = Instant::now() + Duration::from_millis(timeout as u64);
+        let mut event_queues = self.event_queues.lock();
+        // the queue is gone once the job is cancelled
+        while event_queues
+            .get(&run_id)
+            .map_or(false, |queue| queue.is_empty())
+        {
+            if self
+                .event_ready
+                .wait_until(&mut event_queues, deadline)
+                .timed_out()
+            {
+                return None;
+            }
+        }
+        event_queues
+            .get_mut(&run_id)
+            .and_then(|queue| queue.pop_front())
+    }
+
+    fn submit_task<T: Data, U: Data, RT, F>(&self, task: TaskOption, id_in_job: usize)
+    where
+        F: SerFunc((TasKContext, Box<dyn Iterator<Item = T>>)) -> U,
+        RT: Rdd<T> + 'static,
+    {
+        info!("inside submit task");
+        if !self.master {
+            return;
+        }
+        let event_queues = self.event_queues.clone();
+        let event_ready = self.event_ready.clone();
+        let backend = self.backend.clone();
+        let launches = self.task_launches.clone();
+        let key = (task.get_run_id(), task.get_stage_id(), task.get_task_id());
+        let pool = self.job_pools.lock().get(&key.0).cloned();
+        let pool = pool.unwrap_or_else(|| default_pool.to_string());
+        let locations = task.preferred_locations();
+        let run = Box::new(move |executor: usize| {
+            // tasks of a cancelled job which did not start yet are dropped
+            if is_job_cancelled(task.get_run_id()) {
+                return;
+            }
+            let started = Instant::now();
+            // a speculative copy keeps the launch time of the task it copies
+            launches.lock().entry((key.0, key.2)).or_insert(started);
+            let task_bytes = bincode::serialize(&task).unwrap();
+            let result = backend.run_task(executor, &task, task_bytes);
+            let duration = started.elapsed();
+            let (reason, result) = result.into_end_reason();
+            match task {
+                TaskOption::ResultTask(tsk) => {
+                    if let Ok(task_final) = tsk.downcast::<ResultTask<T, U, RT, F>>() {
+                        let task_final = task_final as Box<dyn TaskBase>;
+                        Self::task_ended(
+                            event_queues,
+                            event_ready,
+                            task_final,
+                            reason,
+                            result,
+                            duration,
+                        );
+                    }
+                }
+                TaskOption::ShuffleMapTask(tsk) => {
+                    if let Ok(task_final) = tsk.downcast::<ShuffleMapTask>() {
+                        let task_final = task_final as Box<dyn TaskBase>;
+                        Self::task_ended(
+                            event_queues,
+                            event_ready,
+                            task_final,
+                            reason,
+                            result,
+                            duration,
+                        );
+                    }
+                }
+            };
+        });
+        self.pools.lock().push(&pool, key, locations, run);
+        self.dispatch();
+    }
+
+    // Starts the queued tasks which get a free slot. Each of them runs on the thread pool and
+    // offers its slot to the queued tasks again once it ended.
+    fn dispatch(&self) {
+        let slots = self.pools.lock().total_slots().max(1);
+        self.thread_pool
+            .lock()
+            .get_or_insert_with(|| ThreadPool::new(slots));
+        dispatch(self.pools.clone(), self.thread_pool.clone());
+    }
+}
+
+fn dispatch(pools: Arc<Mutex<SchedulingPools>>, thread_pool: Arc<Mutex<Option<ThreadPool>>>) {
+    let now = Instant::now();
+    loop {
+        let next = pools.lock().pop(now);
+        let (pool, executor, task) = match next {
+            Some(next) => next,
+            None => return,
+        };
+        let (pools, slots) = (pools.clone(), thread_pool.clone());
+        let thread_pool = thread_pool.lock();
+        let thread_pool = thread_pool
+            .as_ref()
+            .expect("thread pool is created before dispatching");
+        thread_pool.execute(move || {
+            let task_id = task.task_id;
+            (task.run)(executor);
+            pools.lock().task_done(&pool, executor, task_id);
+            dispatch(pools, slots);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicBool;
+
+    // Runs the tasks in the current process on executors with one slot each, counting the attempts.
+    #[derive(Clone)]
+    struct CountingBackend {
+        executors: usize,
+        runs: Arc<AtomicUsize>,
+    }
+
+    impl TaskBackend for CountingBackend {
+        fn executors(&self) -> Vec<ExecutorResources> {
+            let executor = ExecutorResources {
+                host: None,
+                cores: 1,
+            };
+            vec![executor; self.executors]
+        }
+
+        fn run_task(&self, _: usize, _: &TaskOption, task_bytes: Vec<u8>) -> TaskResult {
+            self.runs.fetch_add(1, Ordering::SeqCst);
+            bincode::deserialize::<TaskOption>(&task_bytes)
+                .unwrap()
+                .run()
+        }
+    }
+
+    fn counting_scheduler(executors: usize) -> (DAGScheduler<CountingBackend>, Arc<AtomicUsize>) {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let backend = CountingBackend {
+            executors,
+            runs: runs.clone(),
+        };
+        let mut scheduler = DAGScheduler::new(1, true, backend);
+        scheduler.speculation = Some(SpeculationConfig {
+            quantile: 0.25,
+            multiplier: 1.5,
+        });
+        (scheduler, runs)
+    }
+
+    static first_attempt: AtomicBool = AtomicBool::new(true);
+
+    // The first attempt of partition 0 is a straggler.
+    fn slow_first_attempt(split_id: usize) {
+        if split_id == 0 && first_attempt.swap(false, Ordering::SeqCst) {
+            thread::sleep(Duration::from_secs(3));
+        }
+    }
+
+    #[test]
+    fn queued_tasks_are_not_stragglers() {
+        let sc = Context::new("local").unwrap();
+        let rdd = Arc::new(sc.parallelize((0..4).collect::<Vec<i32>>(), 4));
+        let (mut scheduler, runs) = counting_scheduler(1);
+        let func = Fn!(
+            |(_context, iter): (TasKContext, Box<dyn Iterator<Item = i32>>)| {
+                thread::sleep(Duration::from_millis(200));
+                iter.sum::<i32>()
+            }
+        );
+        // tasks wait for the only slot longer than the threshold of 300ms, but only run 200ms
+        let partitions = (0..4).collect();
+        let res = scheduler.run_job(new_run_id(), Arc::new(func), rdd, partitions, false);
+        sc.drop_executors();
+
+        assert_eq!(res.unwrap(), vec![0, 1, 2, 3]);
+        assert_eq!(runs.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn stragglers_get_a_copy_on_another_executor() {
+        let sc = Context::new("local").unwrap();
+        let rdd = Arc::new(sc.parallelize((0..4).collect::<Vec<i32>>(), 4));
+        let (mut scheduler, runs) = counting_scheduler(2);
+        let func = Fn!(
+            |(context, iter): (TasKContext, Box<dyn Iterator<Item = i32>>)| {
+                slow_first_attempt(context.split_id);
+                iter.sum::<i32>()
+            }
+        );
+        let started = Instant::now();
+        let partitions = (0..4).collect();
+        let res = scheduler.run_job(new_run_id(), Arc::new(func), rdd, partitions, false);
+        sc.drop_executors();
+
+        assert_eq!(res.unwrap(), vec![0, 1, 2, 3]);
+        assert_eq!(runs.load(Ordering::SeqCst), 5);
+        assert!(started.elapsed() < Duration::from_millis(2500));
+    }
+
+    #[test]
+    fn narrow_rdds_inherit_preferred_locations() {
+        let sc = Context::new("local").unwrap();
+        let host: Ipv4Addr = "10.0.0.1".parse().unwrap();
+        let files = vec![("a".to_string(), vec![host]), ("b".to_string(), Vec::new())];
+        let lengths = sc
+            .make_rdd_with_locations(files)
+            .map(Fn!(|file: String| file.len()));
+        let (scheduler, _) = counting_scheduler(1);
+        let rdd = lengths.get_rdd_base();
+        let locs = scheduler.get_preferred_locs(rdd.clone(), 0);
+        sc.drop_executors();
+
+        assert_eq!(locs, vec![TaskLocation::Host(host)]);
+        assert!(scheduler.get_preferred_locs(rdd, 1).is_empty());
+    }
+
+    #[test]
+    fn failed_job_cancels_its_tasks() {
+        let sc = Context::new("local").unwrap();
+        let rdd = Arc::new(sc.parallelize((0..4).collect::<Vec<i32>>(), 4));
+        let (mut scheduler, _) = counting_scheduler(2);
+        let func = Fn!(
+            |(context, iter): (TasKContext, Box<dyn Iterator<Item = i32>>)| {
+                if context.split_id == 0 {
+                    panic!("partition 0 failed");
+                }
+                iter.sum::<i32>()
+            }
+        );
+        let run_id = new_run_id();
+        let partitions = (0..4).collect();
+        let res = scheduler.run_job(run_id, Arc::new(func), rdd, partitions, false);
+        sc.drop_executors();
+
+        match res {
+            Err(crate::Error::TaskFailed { .. }) => {}
+            other => panic!("expected a failed task, got {:?}", other),
+        }
+        assert!(is_job_cancelled(run_id));
+    }
+
+    #[test]
+    fn lost_map_output_resubmits_the_map_stage() {
+        let sc = Context::new("local").unwrap();
+        let pairs = (0..100).map(|i| (i % 10, 1)).collect::<Vec<(i32, i32)>>();
+        let counts = sc
+            .parallelize(pairs, 4)
+            .reduce_by_key(Fn!(|(x, y): (i32, i32)| x + y), 2);
+        let counts = Arc::new(counts);
+        let (mut scheduler, _) = counting_scheduler(2);
+        type Pairs = Box<dyn Iterator<Item = (i32, i32)>>;
+        let func = Arc::new(Fn!(
+            |(_context, iter): (TasKContext, Pairs)| iter.collect::<Vec<_>>()
+        ));
+        let collect = |scheduler: &mut DAGScheduler<CountingBackend>| {
+            let rdd = counts.clone();
+            let res = scheduler.run_job(new_run_id(), func.clone(), rdd, vec![0, 1], false);
+            let mut res = res.unwrap().into_iter().flatten().collect::<Vec<_>>();
+            res.sort();
+            res
+        };
+        let first = collect(&mut scheduler);
+        // the map stage is done, so the second job reads its outputs, one of which is gone
+        let shuffle_id = *scheduler.shuffle_to_map_stage.lock().keys().next().unwrap();
+        env::env.shuffle_manager.remove_map_output(shuffle_id, 0);
+        let second = collect(&mut scheduler);
+        let rerun = env::env.shuffle_manager.get_map_output(shuffle_id, 0, 0);
+        sc.drop_executors();
+
+        let expected = (0..10).map(|k| (k, 10)).collect::<Vec<_>>();
+        assert_eq!(first, expected);
+        assert_eq!(second, expected);
+        assert!(rerun.is_ok());
     }
 }
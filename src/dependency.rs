@@ -64,7 +64,12 @@ pub trait ShuffleDependencyTrait: Serialize + Deserialize + Send + Sync {
     //    fn get_partitioner(&self) -> &dyn PartitionerBox;
     fn is_shuffle(&self) -> bool;
     fn get_rdd_base(&self) -> Arc<dyn RddBase>;
-    fn do_shuffle_task(&self, rdd_base: Arc<dyn RddBase>, partition: usize) -> String;
+    fn do_shuffle_task(
+        &self,
+        rdd_base: Arc<dyn RddBase>,
+        partition: usize,
+        split: Box<dyn Split>,
+    ) -> MapStatus;
 }
 
 impl PartialOrd for dyn ShuffleDependencyTrait {
@@ -99,6 +104,9 @@ pub struct ShuffleDependency<K: Data, V: Data, C: Data> {
     pub aggregator: Arc<Aggregator<K, V, C>>,
     #[serde(with = "serde_traitobject")]
     pub partitioner: Box<dyn Partitioner>,
+    // When false, values are not combined on the map side and every record is written to its bucket as is.
+    // Used by grouping operations, since combining there never reduces the amount of shuffled data.
+    pub map_side_combine: bool,
     is_shuffle: bool,
 }
 impl<
@@ -114,6 +122,7 @@ impl<
         rdd_base: Arc<dyn RddBase>,
         aggregator: Arc<Aggregator<K, V, C>>,
         partitioner: Box<dyn Partitioner>,
+        map_side_combine: bool,
     ) -> Self {
         ShuffleDependency {
             shuffle_id,
@@ -121,6 +130,7 @@ impl<
             rdd_base,
             aggregator,
             partitioner,
+            map_side_combine,
             is_shuffle: true,
         }
     }
@@ -140,106 +150,59 @@ impl<K: Data + Eq + Hash, V: Data, C: Data> ShuffleDependencyTrait for ShuffleDe
         self.rdd_base.clone()
     }
 
-    fn do_shuffle_task(&self, rdd_base: Arc<dyn RddBase>, partition: usize) -> String {
+    fn do_shuffle_task(
+        &self,
+        rdd_base: Arc<dyn RddBase>,
+        partition: usize,
+        split: Box<dyn Split>,
+    ) -> MapStatus {
         info!("doing shuffle_task for partition {}", partition);
-        let split = rdd_base.splits()[partition].clone();
         let aggregator = self.aggregator.clone();
         let num_output_splits = self.partitioner.get_num_of_partitions();
         info!("is cogroup rdd{}", self.is_cogroup);
         info!("num of output splits{}", num_output_splits);
         let partitioner = self.partitioner.clone();
-        let mut buckets = (0..num_output_splits)
-            .map(|_| HashMap::new())
-            .collect::<Vec<_>>();
         info!(
             "before rdd base iterator in shuffle map task for partition {}",
             partition
         );
         info!("split index {}", split.get_index());
 
-        let mut count = 0;
         let mut iter = rdd_base.iterator_any(split.clone());
         if self.is_cogroup {
             iter = rdd_base.cogroup_iterator_any(split);
         }
-        for i in iter {
-            count += 1;
-            //            if count % 30000 == 0 {
-            //                info!(
-            //                    "inside rdd base iterator in shuffle map task  count for partition {} {}",
-            //                    partition, count
-            //                );
-            //            }
-            //
-            //            if count == 0 {
-            //                info!(
-            //                    "iterator inside dependency map task before downcasting {:?} ",
-            //                    i,
-            //                );
-            //                info!(
-            //                    "type of K and V is {:?} {:?} ",
-            //                    std::intrinsics::type_name::<K>(),
-            //                    std::intrinsics::type_name::<V>()
-            //                );
-            //            }
-
-            let b = i.into_any().downcast::<(K, V)>().unwrap();
-            let (k, v) = *b;
-            if count == 0 {
-                info!(
-                    "iterator inside dependency map task after downcasting {:?} {:?}",
-                    k, v
-                );
-            }
-            let bucket_id = partitioner.get_partition(&k);
-            let bucket = &mut buckets[bucket_id];
-            let old_v = bucket.get_mut(&k);
-            if old_v.is_none() {
-                bucket.insert(k, Some(aggregator.create_combiner.call((v,))));
-            } else {
-                let old_v = old_v.unwrap();
-                let old = old_v.take().unwrap();
-                let input = ((old, v),);
-                let output = aggregator.merge_value.call(input);
-                *old_v = Some(output);
-            }
-            //            if count < 5 {
-            //                info!(
-            //                    "bucket inside shuffle dependency after count {:?} {:?} ",
-            //                    count, buckets
-            //                );
-            //            }
-        }
+        let iter = iter.map(|i| *i.into_any().downcast::<(K, V)>().unwrap());
 
-        for (i, bucket) in buckets.into_iter().enumerate() {
-            //            let mut file = File::create(file_path.clone()).unwrap();
-            //            let mut contents = String::new();
-            //            file.read_to_string(&mut contents)
-            //                .expect("not able to read");
-            //            println!("file before {:?}", contents);
-            //            let mut file = BufWriter::new(file);
-            let set: Vec<(K, C)> = bucket.into_iter().map(|(k, v)| (k, v.unwrap())).collect();
-            //            println!("{:?}", set);
-            let ser_bytes = bincode::serialize(&set).unwrap();
-            //            file.write_all(&ser_bytes[..])
-            //                .expect("not able to write to file");
-            // currently shuffle cache is unbounded. File write is disabled. This is just for testing and have to revert back to file write as in previous commits.
-            info!(
-                "shuffle dependency map task set in shuffle id, partition,i  {:?} {:?} {:?} {:?} ",
-                set.get(0),
-                self.shuffle_id,
-                partition,
-                i
-            );
-            env::env.shuffle_manager
-                .put_shuffle_cache(self.shuffle_id, partition, i, ser_bytes);
-            //            let mut contents = String::new();
-            //            file.read_to_string(&mut contents)
-            //                .expect("not able to read");
-            //            println!("file after {:?}", contents);
-            //            println!("written to file {:?}", file_path);
+        let writer = SortShuffleWriter::new(
+            self.shuffle_id,
+            partition,
+            aggregator,
+            partitioner,
+            self.map_side_combine,
+            env::config.shuffle_spill_memory,
+            env::config.shuffle_compression,
+        );
+        fn write_records<K: Data + Eq + Hash, V: Data, C: Data>(
+            mut writer: SortShuffleWriter<K, V, C>,
+            iter: impl Iterator<Item = (K, V)>,
+        ) -> std::io::Result<(Vec<u64>, Vec<u64>)> {
+            for (k, v) in iter {
+                writer.insert(k, v)?;
+            }
+            writer.write()
         }
-        env::env.shuffle_manager.get_server_uri()
+        let (sizes, record_counts) = write_records(writer, iter).unwrap_or_else(|e| {
+            panic!(
+                "unable to write map output of shuffle {} partition {}: {}",
+                self.shuffle_id, partition, e
+            )
+        });
+        MapStatus::new(
+            env::env.shuffle_manager.get_server_uri(),
+            &sizes,
+            &record_counts,
+        )
     }
 }
 
@@ -30,6 +30,91 @@ impl Hosts {
     }
 }
 
+// Settings read from environment variables, falling back to defaults when a variable is not set.
+#[derive(Clone, Debug)]
+pub struct Configuration {
+    // root directory under which the spark-local-* directories holding shuffle files are created
+    pub local_dir: String,
+    // map outputs whose total serialized size is below this many bytes are kept in memory instead of written to disk
+    pub shuffle_in_memory_threshold: usize,
+    // memory in bytes a map task may use for buffering shuffle records before spilling them to disk
+    pub shuffle_spill_memory: usize,
+    // codec used for compressing shuffle blocks
+    pub shuffle_compression: CompressionCodec,
+    // number of times fetching a shuffle block is retried before the fetch is reported as failed
+    pub shuffle_fetch_retries: usize,
+    // wait in milliseconds before the first retry of a fetch, doubled for every further retry
+    pub shuffle_fetch_retry_wait: u64,
+    // bytes of fetched shuffle blocks a reduce task may hold in memory before they are deserialized
+    pub shuffle_max_bytes_in_flight: usize,
+    // number of threads fetching shuffle blocks in each reduce task
+    pub shuffle_fetch_parallelism: usize,
+    // whether adjacent small reduce partitions are merged into one task once the map outputs are known
+    pub shuffle_coalesce_partitions: bool,
+    // size in bytes up to which reduce partitions are merged
+    pub shuffle_target_partition_size: u64,
+    // how concurrent jobs share the task slots
+    pub scheduler_mode: SchedulingMode,
+    // min share and weight of the named scheduling pools
+    pub scheduler_pools: PoolConfigs,
+    // task slots an executor advertises to the master
+    pub executor_cores: usize,
+    // milliseconds a task waits for the executor caching its partition before going node local
+    pub locality_wait_process: u64,
+    // milliseconds a task waits for a node local executor before taking any executor
+    pub locality_wait_node: u64,
+    // whether straggling tasks get a speculative copy
+    pub speculation: bool,
+    // fraction of the tasks of a stage which have to finish before its tasks are speculated
+    pub speculation_quantile: f64,
+    // how many times slower than the median a task has to be to get a speculative copy
+    pub speculation_multiplier: f64,
+}
+
+impl Configuration {
+    pub fn from_env() -> Self {
+        Configuration {
+            local_dir: std::env::var("SPARK_LOCAL_DIR")
+                .unwrap_or_else(|_| std::env::temp_dir().to_string_lossy().into_owned()),
+            shuffle_in_memory_threshold: parse_env_var(
+                "SPARK_SHUFFLE_IN_MEMORY_THRESHOLD",
+                64 * 1024,
+            ),
+            shuffle_spill_memory: parse_env_var("SPARK_SHUFFLE_SPILL_MEMORY", 64 * 1024 * 1024),
+            shuffle_compression: parse_env_var("SPARK_SHUFFLE_COMPRESSION", CompressionCodec::Lz4),
+            shuffle_fetch_retries: parse_env_var("SPARK_SHUFFLE_FETCH_RETRIES", 3),
+            shuffle_fetch_retry_wait: parse_env_var("SPARK_SHUFFLE_FETCH_RETRY_WAIT_MS", 200),
+            shuffle_max_bytes_in_flight: parse_env_var(
+                "SPARK_SHUFFLE_MAX_BYTES_IN_FLIGHT",
+                48 * 1024 * 1024,
+            ),
+            shuffle_fetch_parallelism: parse_env_var("SPARK_SHUFFLE_FETCH_PARALLELISM", 10),
+            shuffle_coalesce_partitions: parse_env_var("SPARK_SHUFFLE_COALESCE_PARTITIONS", false),
+            shuffle_target_partition_size: parse_env_var(
+                "SPARK_SHUFFLE_TARGET_PARTITION_SIZE",
+                64 * 1024 * 1024,
+            ),
+            scheduler_mode: parse_env_var("SPARK_SCHEDULER_MODE", SchedulingMode::Fifo),
+            scheduler_pools: parse_env_var("SPARK_SCHEDULER_POOLS", PoolConfigs::default()),
+            executor_cores: parse_env_var("SPARK_EXECUTOR_CORES", num_cpus::get()),
+            locality_wait_process: parse_env_var("SPARK_LOCALITY_WAIT_PROCESS_MS", 3000),
+            locality_wait_node: parse_env_var("SPARK_LOCALITY_WAIT_NODE_MS", 3000),
+            speculation: parse_env_var("SPARK_SPECULATION", false),
+            speculation_quantile: parse_env_var("SPARK_SPECULATION_QUANTILE", 0.75),
+            speculation_multiplier: parse_env_var("SPARK_SPECULATION_MULTIPLIER", 1.5),
+        }
+    }
+}
+
+fn parse_env_var<T: std::str::FromStr>(name: &str, default: T) -> T {
+    match std::env::var(name) {
+        Ok(value) => value
+            .parse()
+            .unwrap_or_else(|_| panic!("Unable to parse the {} environment variable", name)),
+        Err(_) => default,
+    }
+}
+
 impl Env {
     pub fn new(master: bool, master_addr: SocketAddr) -> Self {
         Env {
@@ -63,6 +148,8 @@ lazy_static! {
         Env::new(*is_master, hosts.master)
     };
 
+    pub static ref config: Configuration = Configuration::from_env();
+
     pub static ref local_ip: Ipv4Addr = std::env::var("SPARK_LOCAL_IP")
         .expect("You must set the SPARK_LOCAL_IP environment variable")
         .parse()
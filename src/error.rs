@@ -0,0 +1,96 @@
+use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+// Errors returned to the driver program by actions and context constructors.
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    // a task, result or message could not be (de)serialized
+    Serialization(bincode::Error),
+    // the master could not talk to an executor
+    Network(String),
+    // a task kept failing until the job was aborted
+    TaskFailed {
+        task_id: usize,
+        stage_id: usize,
+        failures: usize,
+        cause: String,
+    },
+    JobAborted(String),
+    // the job was cancelled through Context::cancel_job or cancel_all_jobs
+    JobCancelled(usize),
+    // the job was not done within the timeout given to JobHandle::wait_timeout
+    JobTimedOut {
+        job_id: usize,
+        timeout: Duration,
+    },
+    // invalid or missing configuration, like an unreadable hosts.conf
+    Config(String),
+    // the action is not defined for the RDD, like first() on an empty RDD
+    UnsupportedOperation(&'static str),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "io error: {}", e),
+            Error::Serialization(e) => write!(f, "serialization error: {}", e),
+            Error::Network(msg) => write!(f, "network error: {}", msg),
+            Error::TaskFailed {
+                task_id,
+                stage_id,
+                failures,
+                cause,
+            } => write!(
+                f,
+                "Job aborted: task {} of stage {} failed {} times, most recent failure: {}",
+                task_id, stage_id, failures, cause
+            ),
+            Error::JobAborted(msg) => write!(f, "Job aborted: {}", msg),
+            Error::JobCancelled(job_id) => write!(f, "job {} was cancelled", job_id),
+            Error::JobTimedOut { job_id, timeout } => {
+                write!(f, "job {} timed out after {:?}", job_id, timeout)
+            }
+            Error::Config(msg) => write!(f, "configuration error: {}", msg),
+            Error::UnsupportedOperation(msg) => write!(f, "unsupported operation: {}", msg),
+        }
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Serialization(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<bincode::Error> for Error {
+    fn from(e: bincode::Error) -> Self {
+        Error::Serialization(e)
+    }
+}
+
+impl From<capnp::Error> for Error {
+    fn from(e: capnp::Error) -> Self {
+        Error::Network(e.to_string())
+    }
+}
+
+impl From<toml::de::Error> for Error {
+    fn from(e: toml::de::Error) -> Self {
+        Error::Config(e.to_string())
+    }
+}
@@ -1,11 +1,53 @@
 use super::*;
 use capnp::serialize_packed;
-use std::net::TcpListener;
-//use std::net::TcpStream;
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
 use std::thread;
 use std::time::SystemTime;
 use threadpool::ThreadPool;
 
+// Control messages sent by the master to the end signal port of an executor.
+#[derive(Serialize, Deserialize, Debug)]
+pub enum ExecutorMessage {
+    // the shuffle is not referenced anymore, so its map outputs can be deleted
+    RemoveShuffle(usize),
+    // the job was cancelled, so its running tasks should stop
+    CancelJob(usize),
+    // sent by the master once the executor is up, answered with its ExecutorInfo
+    Register,
+    Exit,
+}
+
+// Sends a control message to every executor, given by its address and task port. Executors which
+// can't be reached are skipped.
+pub(crate) fn send_to_executors<'a>(
+    executors: impl Iterator<Item = (&'a str, usize)>,
+    msg: &ExecutorMessage,
+) {
+    let msg = bincode::serialize(msg).unwrap();
+    for (address, port) in executors {
+        // the end signal port of an executor is its task port + 10
+        let mut stream = match TcpStream::connect(format!("{}:{}", address, port + 10)) {
+            Ok(stream) => stream,
+            Err(e) => {
+                error!("couldn't connect to executor {}:{} {}", address, port, e);
+                continue;
+            }
+        };
+        let mut message = ::capnp::message::Builder::new_default();
+        let mut task_data = message.init_root::<serialized_data::Builder>();
+        task_data.set_msg(&msg);
+        serialize_packed::write_message(&mut stream, &message);
+    }
+}
+
+// Resources an executor advertises to the master when it registers.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct ExecutorInfo {
+    // number of tasks the executor runs at once, SPARK_EXECUTOR_CORES or the number of cpus
+    pub cores: usize,
+}
+
 pub struct Executor {
     port: usize,
     //    thread_pool: ThreadPool,
@@ -13,6 +55,15 @@ pub struct Executor {
 
 impl Executor {
     pub fn new(port: usize) -> Self {
+        // The master copies the binary to a spark-binary-* directory before starting the executor.
+        if let Some(binary_dir) = Executor::binary_dir() {
+            if let Some(root) = binary_dir.parent() {
+                sweep_stale_dirs(&root.to_string_lossy(), "spark-binary-");
+            }
+            if let Err(e) = write_pid_file(&binary_dir) {
+                error!("unable to write pid file in {:?}: {}", binary_dir, e);
+            }
+        }
         Executor {
             port,
             //            thread_pool: ThreadPool::new(1),
@@ -36,8 +87,8 @@ impl Executor {
         let server_port = self.port;
 
         thread::spawn(move || {
-            //TODO change hardcoded value to number of cores in the system
-            let threads = num_cpus::get();
+            // as many threads as the cores advertised to the master, which never sends more tasks
+            let threads = env::config.executor_cores;
             let thread_pool = ThreadPool::new(threads);
             for stream in listener.incoming() {
                 match stream {
@@ -73,53 +124,20 @@ impl Executor {
                             //                            let task_dir_path =
                             //                                format!("{}/spark-task-{}/task", local_dir_root, local_dir_uuid);
                             //                            let mut f = fs::File::create(task_dir_path.clone()).unwrap();
-                            let msg = match task_data.get_msg() {
-                                Ok(s) => {
+                            let result = match task_data.get_msg() {
+                                Ok(msg) => {
                                     info!("got the task in executor",);
-                                    s
+                                    Executor::run_task(server_port, msg, now)
                                 }
                                 Err(e) => {
-                                    info!("problem in getting the task in executor {:?}", e);
-                                    std::process::exit(0);
+                                    error!("problem in getting the task in executor {:?}", e);
+                                    TaskResult::Failed(TaskFailure::new(format!(
+                                        "unable to read the task: {}",
+                                        e
+                                    )))
                                 }
                             };
-                            //                            f.write(msg);
-                            //                            let f = fs::File::open(task_dir_path.clone()).unwrap();
-                            //                            let mut f = fs::File::open(task_dir_path).unwrap();
-                            //                            let mut buffer = vec![0; msg.len()];
-
-                            //                            f.read(&mut buffer).unwrap();
-                            let des_task: TaskOption = match bincode::deserialize(&msg) {
-                                Ok(s) => {
-                                    info!("serialized the task in executor",);
-                                    s
-                                }
-                                Err(e) => {
-                                    info!("problem in serializing the task in executor {:?}", e);
-                                    std::process::exit(0);
-                                }
-                            };
-                            info!(
-                                "task in executor {:?} {} slave task id",
-                                server_port,
-                                des_task.get_task_id(),
-                            );
                             std::mem::drop(task_data);
-                            info!(
-                                "time taken in server for deserializing:{} {}",
-                                server_port,
-                                now.elapsed().unwrap().as_millis(),
-                            );
-                            let now = SystemTime::now();
-                            info!("executing the trait from server port {}", server_port);
-                            //TODO change attempt id from 0 to proper value
-                            let result = des_task.run(0);
-
-                            info!(
-                                "time taken in server for running:{} {}",
-                                server_port,
-                                now.elapsed().unwrap().as_millis(),
-                            );
                             let now = SystemTime::now();
                             let result = bincode::serialize(&result).unwrap();
                             info!(
@@ -144,7 +162,43 @@ impl Executor {
         });
     }
 
-    // A thread listening for exit signal from master to end the whole slave process
+    // Deserializes and runs a task. Panics in the task and malformed tasks come back as failed results.
+    fn run_task(server_port: usize, msg: &[u8], now: SystemTime) -> TaskResult {
+        let des_task: TaskOption = match bincode::deserialize(msg) {
+            Ok(s) => {
+                info!("serialized the task in executor",);
+                s
+            }
+            Err(e) => {
+                error!("problem in serializing the task in executor {:?}", e);
+                return TaskResult::Failed(TaskFailure::new(format!(
+                    "unable to deserialize the task: {}",
+                    e
+                )));
+            }
+        };
+        info!(
+            "task in executor {:?} {} slave task id",
+            server_port,
+            des_task.get_task_id(),
+        );
+        info!(
+            "time taken in server for deserializing:{} {}",
+            server_port,
+            now.elapsed().unwrap().as_millis(),
+        );
+        let now = SystemTime::now();
+        info!("executing the trait from server port {}", server_port);
+        let result = des_task.run();
+        info!(
+            "time taken in server for running:{} {}",
+            server_port,
+            now.elapsed().unwrap().as_millis(),
+        );
+        result
+    }
+
+    // A thread listening for control messages from master, like the exit signal which ends the whole slave process
     pub fn exit_signal(&self) {
         let listener = match TcpListener::bind(format!("0.0.0.0:{}", self.port + 10,)) {
             Ok(s) => {
@@ -179,14 +233,62 @@ impl Executor {
                     let signal_data = message_reader
                         .get_root::<serialized_data::Reader>()
                         .unwrap();
-                    info!("got end signal inside server");
-                    let signal: bool =
+                    info!("got control message inside server");
+                    let msg: ExecutorMessage =
                         bincode::deserialize(signal_data.get_msg().unwrap()).unwrap();
-                    if signal {
-                        return;
+                    info!("control message {:?}", msg);
+                    match msg {
+                        ExecutorMessage::RemoveShuffle(shuffle_id) => {
+                            info!("removing shuffle {} in executor", shuffle_id);
+                            env::env.map_output_tracker.unregister_shuffle(shuffle_id);
+                            env::env.shuffle_manager.remove_shuffle(shuffle_id);
+                        }
+                        ExecutorMessage::CancelJob(run_id) => {
+                            info!("cancelling job {} in executor", run_id);
+                            cancel_job_locally(run_id);
+                        }
+                        ExecutorMessage::Register => {
+                            let info = ExecutorInfo {
+                                cores: env::config.executor_cores,
+                            };
+                            info!("registering with master as {:?}", info);
+                            let info = bincode::serialize(&info).unwrap();
+                            let mut message = ::capnp::message::Builder::new_default();
+                            let mut info_data = message.init_root::<serialized_data::Builder>();
+                            info_data.set_msg(&info);
+                            serialize_packed::write_message(&mut stream, &message);
+                        }
+                        ExecutorMessage::Exit => {
+                            self.clean_up();
+                            return;
+                        }
                     }
                 }
             }
         }
     }
+
+    // Removes the local directory and the directory the binary was copied to.
+    fn clean_up(&self) {
+        env::env.shuffle_manager.clean_up();
+        if let Some(binary_dir) = Executor::binary_dir() {
+            match std::fs::remove_dir_all(&binary_dir) {
+                Ok(_) => info!("removed binary directory {:?}", binary_dir),
+                Err(e) => error!("unable to remove binary directory {:?}: {}", binary_dir, e),
+            }
+        }
+    }
+
+    fn binary_dir() -> Option<PathBuf> {
+        let binary_dir = std::env::current_exe().ok()?.parent()?.to_path_buf();
+        let is_copied = binary_dir
+            .file_name()
+            .and_then(|name| name.to_str())
+            .map_or(false, |name| name.starts_with("spark-binary-"));
+        if is_copied {
+            Some(binary_dir)
+        } else {
+            None
+        }
+    }
 }
@@ -0,0 +1,233 @@
+use super::*;
+use std::collections::HashMap;
+use std::fs;
+use std::hash::Hash;
+use std::io::{BufReader, BufWriter, Write};
+use std::iter::Peekable;
+use std::path::PathBuf;
+
+// Append only map used for aggregating on the reduce side, which spills to disk when the estimated size
+// of the in memory map exceeds the memory threshold.
+// Since keys are only required to be hashable, spilled chunks are sorted by the hash of the key. When iterating,
+// the chunks and the remaining in memory map are merged by hash, and records with equal keys are combined using `merge`.
+pub(crate) struct ExternalAppendOnlyMap<K: Data + Eq + Hash, C: Data, F>
+where
+    F: Fn(C, C) -> C,
+{
+    current: HashMap<K, Option<C>>,
+    merge: F,
+    memory_threshold: usize,
+    inserts: usize,
+    avg_record_size: f64,
+    spills: Vec<SpilledChunk>,
+}
+
+struct SpilledChunk {
+    path: PathBuf,
+    num_records: u64,
+}
+
+impl<K: Data + Eq + Hash, C: Data, F> ExternalAppendOnlyMap<K, C, F>
+where
+    F: Fn(C, C) -> C + 'static,
+{
+    pub fn new(merge: F, memory_threshold: usize) -> Self {
+        ExternalAppendOnlyMap {
+            current: HashMap::new(),
+            merge,
+            memory_threshold,
+            inserts: 0,
+            avg_record_size: 0.0,
+            spills: Vec::new(),
+        }
+    }
+
+    pub fn insert(&mut self, k: K, c: C) {
+        self.inserts += 1;
+        let sample = self.inserts % SAMPLE_INTERVAL == 0 || self.current.is_empty();
+        if let Some(old_c) = self.current.get_mut(&k) {
+            let old = old_c.take().unwrap();
+            let output = (self.merge)(old, c);
+            if sample {
+                self.avg_record_size = update_avg(self.avg_record_size, record_size(&k, &output));
+            }
+            *old_c = Some(output);
+        } else {
+            if sample {
+                self.avg_record_size = update_avg(self.avg_record_size, record_size(&k, &c));
+            }
+            self.current.insert(k, Some(c));
+        }
+        if sample
+            && (self.avg_record_size * self.current.len() as f64) as usize > self.memory_threshold
+        {
+            self.spill()
+                .unwrap_or_else(|e| panic!("unable to spill aggregation map to disk: {}", e));
+        }
+    }
+
+    fn sorted_current(&mut self) -> Vec<(u64, K, C)> {
+        let mut records = self
+            .current
+            .drain()
+            .map(|(k, c)| (hash(&k), k, c.unwrap()))
+            .collect::<Vec<_>>();
+        records.sort_by_key(|r| r.0);
+        records
+    }
+
+    fn spill(&mut self) -> std::io::Result<()> {
+        let records = self.sorted_current();
+        let (path, file) = env::env.shuffle_manager.create_temp_file()?;
+        let chunk = SpilledChunk {
+            path,
+            num_records: records.len() as u64,
+        };
+        let mut writer = BufWriter::new(file);
+        for (_, k, c) in records {
+            bincode::serialize_into(&mut writer, &(k, c))
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+        }
+        writer.flush()?;
+        info!(
+            "spilled {} records of aggregation map to {:?}",
+            chunk.num_records, chunk.path
+        );
+        self.spills.push(chunk);
+        Ok(())
+    }
+
+    /// Consumes the map and returns the combined records. Spilled chunks are read back lazily and removed afterwards.
+    pub fn into_combined_iter(mut self) -> Box<dyn Iterator<Item = (K, C)>> {
+        if self.spills.is_empty() {
+            return Box::new(self.current.into_iter().map(|(k, c)| (k, c.unwrap())));
+        }
+        let mut streams: Vec<Peekable<Box<dyn Iterator<Item = (u64, K, C)>>>> = Vec::new();
+        for chunk in self.spills.drain(..) {
+            let stream = SpillReader::<K, C>::new(chunk)
+                .unwrap_or_else(|e| panic!("unable to read spilled aggregation map: {}", e));
+            streams.push((Box::new(stream) as Box<dyn Iterator<Item = (u64, K, C)>>).peekable());
+        }
+        let in_memory = self.sorted_current();
+        streams.push((Box::new(in_memory.into_iter()) as Box<dyn Iterator<Item = _>>).peekable());
+        Box::new(MergeIterator {
+            streams,
+            merge: self.merge,
+            pending: Vec::new(),
+        })
+    }
+}
+
+// Reads back the records of a spilled chunk and removes the file once dropped.
+struct SpillReader<K, C> {
+    reader: BufReader<fs::File>,
+    remaining: u64,
+    chunk: SpilledChunk,
+    _marker: std::marker::PhantomData<(K, C)>,
+}
+
+impl<K: Data + Hash, C: Data> SpillReader<K, C> {
+    fn new(chunk: SpilledChunk) -> std::io::Result<Self> {
+        let reader = BufReader::new(fs::File::open(&chunk.path)?);
+        Ok(SpillReader {
+            reader,
+            remaining: chunk.num_records,
+            chunk,
+            _marker: std::marker::PhantomData,
+        })
+    }
+}
+
+impl<K: Data + Hash, C: Data> Iterator for SpillReader<K, C> {
+    type Item = (u64, K, C);
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let (k, c): (K, C) = bincode::deserialize_from(&mut self.reader).unwrap_or_else(|e| {
+            panic!(
+                "unable to deserialize spilled record from {:?}: {}",
+                self.chunk.path, e
+            )
+        });
+        Some((hash(&k), k, c))
+    }
+}
+
+impl Drop for SpilledChunk {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            info!("unable to remove spill file {:?}: {}", self.path, e);
+        }
+    }
+}
+
+// Merges streams sorted by key hash. All records with the smallest hash are taken from every stream,
+// and those among them with equal keys are combined before being returned.
+struct MergeIterator<K, C, F> {
+    streams: Vec<Peekable<Box<dyn Iterator<Item = (u64, K, C)>>>>,
+    merge: F,
+    pending: Vec<(K, C)>,
+}
+
+impl<K: Data + Eq, C: Data, F> Iterator for MergeIterator<K, C, F>
+where
+    F: Fn(C, C) -> C,
+{
+    type Item = (K, C);
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(record) = self.pending.pop() {
+            return Some(record);
+        }
+        let min_hash = self
+            .streams
+            .iter_mut()
+            .filter_map(|stream| stream.peek().map(|r| r.0))
+            .min()?;
+        for stream in self.streams.iter_mut() {
+            while stream.peek().map_or(false, |r| r.0 == min_hash) {
+                let (_, k, c) = stream.next().unwrap();
+                match self.pending.iter().position(|(pk, _)| *pk == k) {
+                    Some(pos) => {
+                        let (pk, pc) = self.pending.swap_remove(pos);
+                        self.pending.push((pk, (self.merge)(pc, c)));
+                    }
+                    None => self.pending.push((k, c)),
+                }
+            }
+        }
+        self.pending.pop()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spilled_chunks_are_merged_like_an_in_memory_map() {
+        let records = (0..5000)
+            .map(|i| (i % 500, i as i64))
+            .collect::<Vec<(i32, i64)>>();
+        let mut map = ExternalAppendOnlyMap::new(|c1: i64, c2: i64| c1 + c2, 64);
+        let mut expected = HashMap::new();
+        for (k, c) in records {
+            map.insert(k, c);
+            *expected.entry(k).or_insert(0) += c;
+        }
+        assert!(map.spills.len() > 1, "{} spills", map.spills.len());
+        let paths = map
+            .spills
+            .iter()
+            .map(|chunk| chunk.path.clone())
+            .collect::<Vec<_>>();
+
+        let mut combined = HashMap::new();
+        for (k, c) in map.into_combined_iter() {
+            assert!(combined.insert(k, c).is_none(), "key {} returned twice", k);
+        }
+        assert_eq!(combined, expected);
+        assert!(paths.iter().all(|path| !path.exists()));
+    }
+}
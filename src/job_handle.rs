@@ -0,0 +1,139 @@
+use super::*;
+use parking_lot::{Condvar, Mutex};
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context as FutureContext, Poll, Waker};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Progress of a running job, counted in tasks of its final stage.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct JobProgress {
+    pub finished_tasks: usize,
+    pub total_tasks: usize,
+}
+
+struct JobState<R> {
+    result: Option<Result<R>>,
+    done: bool,
+    waker: Option<Waker>,
+}
+
+// A job running in the background, returned by the async actions and Context::submit_job.
+// It resolves to the result of the action once the job is done.
+pub struct JobHandle<R> {
+    job_id: usize,
+    context: Context,
+    state: Arc<(Mutex<JobState<R>>, Condvar)>,
+}
+
+impl<R: Send + 'static> JobHandle<R> {
+    // Runs the job on its own thread, so that neither the caller nor an async runtime is blocked.
+    pub(crate) fn spawn<J>(context: Context, job: J) -> Self
+    where
+        J: FnOnce(Context, usize) -> Result<R> + Send + 'static,
+    {
+        let job_id = new_run_id();
+        let state = Arc::new((
+            Mutex::new(JobState {
+                result: None,
+                done: false,
+                waker: None,
+            }),
+            Condvar::new(),
+        ));
+        let job_state = state.clone();
+        let job_context = context.clone();
+        // the job runs in the pool set on the submitting thread
+        let pool = current_pool();
+        thread::spawn(move || {
+            set_current_pool(pool);
+            let result = panic::catch_unwind(AssertUnwindSafe(|| job(job_context, job_id)))
+                .unwrap_or_else(|_| Err(Error::JobAborted(format!("job {} panicked", job_id))));
+            let (lock, done) = &*job_state;
+            let mut state = lock.lock();
+            state.result = Some(result);
+            state.done = true;
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+            done.notify_all();
+        });
+        JobHandle {
+            job_id,
+            context,
+            state,
+        }
+    }
+
+    pub fn job_id(&self) -> usize {
+        self.job_id
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.state.0.lock().done
+    }
+
+    /// Progress of the job, or None if it has not started yet or is already done.
+    pub fn progress(&self) -> Option<JobProgress> {
+        self.context.job_progress(self.job_id)
+    }
+
+    /// Cancels the job, which then resolves to `Error::JobCancelled`.
+    pub fn cancel(&self) {
+        self.context.cancel_job(self.job_id);
+    }
+
+    /// Blocks the current thread until the job is done.
+    pub fn wait(self) -> Result<R> {
+        let (lock, done) = &*self.state;
+        let mut state = lock.lock();
+        while !state.done {
+            done.wait(&mut state);
+        }
+        state.result.take().unwrap_or_else(|| {
+            Err(Error::JobAborted(format!(
+                "result of job {} was already taken",
+                self.job_id
+            )))
+        })
+    }
+
+    /// Like wait, but cancels the job if it is not done within the timeout and returns
+    /// `Error::JobTimedOut` then.
+    pub fn wait_timeout(self, timeout: Duration) -> Result<R> {
+        let deadline = Instant::now() + timeout;
+        let timed_out = {
+            let (lock, done) = &*self.state;
+            let mut state = lock.lock();
+            while !state.done && !done.wait_until(&mut state, deadline).timed_out() {}
+            !state.done
+        };
+        if !timed_out {
+            return self.wait();
+        }
+        info!("job {} timed out", self.job_id);
+        self.cancel();
+        let job_id = self.job_id;
+        // the job ends soon after being cancelled, its result is of no use anymore
+        let _ = self.wait();
+        Err(Error::JobTimedOut { job_id, timeout })
+    }
+}
+
+impl<R> Future for JobHandle<R> {
+    type Output = Result<R>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut FutureContext) -> Poll<Self::Output> {
+        let mut state = self.state.0.lock();
+        match state.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
@@ -20,7 +20,7 @@ extern crate serde_closure;
 #[macro_use]
 extern crate lazy_static;
 extern crate capnp;
-use log::info;
+use log::{error, info};
 use std::io::prelude::*;
 pub mod serialized_data_capnp {
     include!(concat!(env!("OUT_DIR"), "/capnp/serialized_data_capnp.rs"));
@@ -33,7 +33,14 @@ use serialized_data_capnp::serialized_data;
 pub mod context;
 pub use context::*;
 
+pub mod error;
+pub use error::{Error, Result};
+
+pub mod job_handle;
+pub use job_handle::*;
+
 mod dependency;
+pub use dependency::Dependency;
 use dependency::*;
 
 mod executor;
@@ -48,6 +55,12 @@ pub use rdd::*;
 pub mod pair_rdd;
 pub use pair_rdd::*;
 
+pub mod numeric_rdd;
+pub use numeric_rdd::*;
+
+pub mod stat_counter;
+pub use stat_counter::*;
+
 mod shuffled_rdd;
 use shuffled_rdd::*;
 
@@ -66,22 +79,42 @@ use cache_tracker::*;
 mod cache;
 use cache::*;
 
+mod bounded_priority_queue;
+use bounded_priority_queue::*;
+
 mod shuffle_fetcher;
 use shuffle_fetcher::*;
 
 mod shuffle_manager;
 use shuffle_manager::*;
 
+mod shuffle_block;
+pub use shuffle_block::CompressionCodec;
+use shuffle_block::*;
+
+mod sort_shuffle_writer;
+use sort_shuffle_writer::*;
+
+mod external_append_only_map;
+use external_append_only_map::*;
+
 mod shuffle_map_task;
 use shuffle_map_task::*;
 
-mod scheduler;
-use scheduler::*;
-
 mod dag_scheduler;
 use dag_scheduler::*;
 
+mod scheduling_pool;
+use scheduling_pool::*;
+
+mod task_slots;
+use task_slots::*;
+
+mod speculation;
+use speculation::*;
+
 mod task;
+pub use task::TasKContext;
 use task::*;
 
 mod local_scheduler;
@@ -102,10 +135,8 @@ use map_output_tracker::*;
 mod result_task;
 use result_task::*;
 
-mod job;
-use job::*;
-
 mod serializable_traits;
+pub use serializable_traits::Box as SerBox;
 use serializable_traits::{AnyData, Data, Func, SerFunc};
 
 mod env;
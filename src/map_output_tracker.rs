@@ -15,11 +15,98 @@ pub enum MapOutputTrackerMessage {
     StopMapOutputTracker,
 }
 
+// Result of a shuffle map task: where its output is served from, and the size in bytes and the record count
+// of every bucket. Sizes and counts are stored with one byte each, see compress_size, since a shuffle has
+// num_maps * num_reduces of them and all are sent to every reduce task.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MapStatus {
+    pub server_uri: String,
+    compressed_sizes: Vec<u8>,
+    compressed_record_counts: Vec<u8>,
+}
+
+impl MapStatus {
+    pub fn new(server_uri: String, sizes: &[u64], record_counts: &[u64]) -> Self {
+        MapStatus {
+            server_uri,
+            compressed_sizes: sizes.iter().map(|&size| compress_size(size)).collect(),
+            compressed_record_counts: record_counts
+                .iter()
+                .map(|&count| compress_size(count))
+                .collect(),
+        }
+    }
+
+    /// Estimated size in bytes of the bucket `reduce_id`.
+    pub fn get_size_for_block(&self, reduce_id: usize) -> u64 {
+        decompress_size(self.compressed_sizes[reduce_id])
+    }
+
+    /// Estimated number of records in the bucket `reduce_id`. Empty buckets are always reported as 0.
+    pub fn get_record_count_for_block(&self, reduce_id: usize) -> u64 {
+        decompress_size(self.compressed_record_counts[reduce_id])
+    }
+}
+
+const LOG_BASE: f64 = 1.1;
+
+// Encodes a size as the exponent of its next power of 1.1, which keeps the error below 10% for sizes up to ~35 GB.
+// Zero is kept exact, so that empty buckets can be skipped.
+pub(crate) fn compress_size(size: u64) -> u8 {
+    if size == 0 {
+        0
+    } else if size <= 1 {
+        1
+    } else {
+        let exponent = ((size as f64).ln() / LOG_BASE.ln()).ceil();
+        exponent.min(255.0) as u8
+    }
+}
+
+pub(crate) fn decompress_size(compressed: u8) -> u64 {
+    if compressed == 0 {
+        0
+    } else {
+        LOG_BASE.powi(i32::from(compressed)) as u64
+    }
+}
+
+// Estimated totals of a shuffle per reduce partition, summed over all map outputs.
+#[derive(Clone, Debug)]
+pub struct MapOutputStatistics {
+    pub shuffle_id: usize,
+    pub bytes_by_partition: Vec<u64>,
+    pub records_by_partition: Vec<u64>,
+}
+
+// Groups adjacent reduce partitions into ranges of at most `target_size` bytes, a partition larger than the
+// target gets a range of its own. The ranges are half open and cover all partitions in order.
+pub(crate) fn coalesce_partitions(
+    bytes_by_partition: &[u64],
+    target_size: u64,
+) -> Vec<(usize, usize)> {
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    let mut size = 0;
+    for (reduce_id, &bytes) in bytes_by_partition.iter().enumerate() {
+        if reduce_id > start && size + bytes > target_size {
+            ranges.push((start, reduce_id));
+            start = reduce_id;
+            size = 0;
+        }
+        size += bytes;
+    }
+    if start < bytes_by_partition.len() {
+        ranges.push((start, bytes_by_partition.len()));
+    }
+    ranges
+}
+
 // starts the server in master node and client in slave nodes. Similar to cache tracker
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct MapOutputTracker {
     pub is_master: bool,
-    server_uris: Arc<RwLock<HashMap<usize, Vec<Option<String>>>>>,
+    map_statuses: Arc<RwLock<HashMap<usize, Vec<Option<MapStatus>>>>>,
     fetching: Arc<RwLock<HashSet<usize>>>,
     generation: Arc<Mutex<i64>>,
     master_addr: SocketAddr,
@@ -30,7 +117,7 @@ impl Default for MapOutputTracker {
     fn default() -> Self {
         MapOutputTracker {
             is_master: Default::default(),
-            server_uris: Default::default(),
+            map_statuses: Default::default(),
             fetching: Default::default(),
             generation: Default::default(),
             master_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 0),
@@ -42,7 +129,7 @@ impl MapOutputTracker {
     pub fn new(is_master: bool, master_addr: SocketAddr) -> Self {
         let m = MapOutputTracker {
             is_master,
-            server_uris: Arc::new(RwLock::new(HashMap::new())),
+            map_statuses: Arc::new(RwLock::new(HashMap::new())),
             fetching: Arc::new(RwLock::new(HashSet::new())),
             generation: Arc::new(Mutex::new(0)),
             master_addr,
@@ -51,7 +138,7 @@ impl MapOutputTracker {
         m
     }
 
-    fn client(&self, shuffle_id: usize) -> Vec<String> {
+    fn client(&self, shuffle_id: usize) -> Vec<MapStatus> {
         //        if !self.is_master {
 
         while let Err(_) = TcpStream::connect(self.master_addr) {
@@ -73,8 +160,9 @@ impl MapOutputTracker {
         let shuffle_data = message_reader
             .get_root::<serialized_data::Reader>()
             .unwrap();
-        let locs: Vec<String> = bincode::deserialize(&shuffle_data.get_msg().unwrap()).unwrap();
-        locs
+        let statuses: Vec<MapStatus> =
+            bincode::deserialize(&shuffle_data.get_msg().unwrap()).unwrap();
+        statuses
         //        }
         //        else {
         //
@@ -94,7 +182,7 @@ impl MapOutputTracker {
         if self.is_master {
             info!("mapoutput tracker server starting");
             let master_addr = self.master_addr.clone();
-            let server_uris = self.server_uris.clone();
+            let map_statuses = self.map_statuses.clone();
             thread::spawn(move || {
                 let listener = TcpListener::bind(master_addr).unwrap();
                 info!("mapoutput tracker server started");
@@ -102,7 +190,7 @@ impl MapOutputTracker {
                     match stream {
                         Err(_) => continue,
                         Ok(mut stream) => {
-                            let server_uris_clone = server_uris.clone();
+                            let map_statuses_clone = map_statuses.clone();
                             thread::spawn(move || {
                                 //reading
                                 let r = ::capnp::message::ReaderOptions {
@@ -120,20 +208,18 @@ impl MapOutputTracker {
                                     .unwrap();
                                 let shuffle_id: usize =
                                     bincode::deserialize(data.get_msg().unwrap()).unwrap();
-                                while server_uris_clone
+                                // Waits until the outputs of all map tasks are known. Outputs can be missing
+                                // while the map stage is still running, or recomputed after a fetch failure.
+                                while !map_statuses_clone
                                     .read()
                                     .get(&shuffle_id)
-                                    .unwrap()
-                                    .iter()
-                                    .filter(|x| !x.is_none())
-                                    .count()
-                                    == 0
+                                    .map_or(false, |locs| locs.iter().all(|x| x.is_some()))
                                 {
                                     //check whether this will hurt the performance or not
                                     let wait = time::Duration::from_millis(1);
                                     thread::sleep(wait);
                                 }
-                                let locs = server_uris_clone
+                                let locs = map_statuses_clone
                                     .read()
                                     .get(&shuffle_id)
                                     .unwrap_or(&Vec::new())
@@ -148,7 +234,7 @@ impl MapOutputTracker {
                                         x.unwrap().clone()
                                     })
                                     .collect::<Vec<_>>();
-                                info!("locs inside mapoutput tracker server after unwrapping for shuffle id {:?} {:?} ", shuffle_id, locs);
+                                info!("locs inside mapoutput tracker server after unwrapping for shuffle id {:?} {:?} ", shuffle_id, locs.iter().map(|x| &x.server_uri).collect::<Vec<_>>());
 
                                 //writing
                                 let result = bincode::serialize(&locs).unwrap();
@@ -166,25 +252,25 @@ impl MapOutputTracker {
 
     pub fn register_shuffle(&self, shuffle_id: usize, num_maps: usize) {
         info!("inside register shuffle");
-        if !self.server_uris.read().get(&shuffle_id).is_none() {
+        if !self.map_statuses.read().get(&shuffle_id).is_none() {
             //TODO error handling
             info!("map tracker register shuffle none");
             return;
         }
-        let mut server_uris = self.server_uris.write();
-        server_uris.insert(shuffle_id, vec![None; num_maps]);
-        info!("server_uris after register_shuffle {:?}", server_uris);
+        let mut map_statuses = self.map_statuses.write();
+        map_statuses.insert(shuffle_id, vec![None; num_maps]);
+        info!("map_statuses after register_shuffle {:?}", map_statuses);
     }
 
-    pub fn register_map_output(&self, shuffle_id: usize, map_id: usize, server_uri: String) {
+    pub fn register_map_output(&self, shuffle_id: usize, map_id: usize, status: MapStatus) {
         //        if !self.is_master {
         //            return;
         //        }
-        let mut array = self.server_uris.write();
-        array.get_mut(&shuffle_id).unwrap()[map_id] = Some(server_uri);
+        let mut array = self.map_statuses.write();
+        array.get_mut(&shuffle_id).unwrap()[map_id] = Some(status);
     }
 
-    pub fn register_map_outputs(&self, shuffle_id: usize, locs: Vec<Option<String>>) {
+    pub fn register_map_outputs(&self, shuffle_id: usize, locs: Vec<Option<MapStatus>>) {
         //        if !self.is_master {
         //            let fetched = self.client(shuffle_id);
         //            println!("fetched locs from client {:?}", fetched);
@@ -198,7 +284,7 @@ impl MapOutputTracker {
             "registering map outputs inside map output tracker for shuffle id {} {:?}",
             shuffle_id, locs
         );
-        self.server_uris.write().insert(shuffle_id, locs);
+        self.map_statuses.write().insert(shuffle_id, locs);
         //        .insert(shuffle_id, locs.into_iter().map(|x| Some(x)).collect());
     }
 
@@ -206,26 +292,75 @@ impl MapOutputTracker {
         //        if !self.is_master {
         //            return;
         //        }
-        let array = self.server_uris.read();
-        let array = array.get(&shuffle_id);
-        if !array.is_none() {
-            if array.unwrap().get(map_id).unwrap() == &Some(server_uri) {
-                self.server_uris
-                    .write()
-                    .get_mut(&shuffle_id)
-                    .unwrap()
-                    .insert(map_id, None)
+        let mut map_statuses = self.map_statuses.write();
+        match map_statuses.get_mut(&shuffle_id) {
+            Some(locs) => {
+                let registered = locs
+                    .get(map_id)
+                    .and_then(|status| status.as_ref())
+                    .map_or(false, |status| status.server_uri == server_uri);
+                if registered {
+                    locs[map_id] = None;
+                }
+                self.increment_generation();
             }
-            self.increment_generation();
-        } else {
-            //TODO error logging
+            None => info!(
+                "unregistering map output {} of unknown shuffle {}",
+                map_id, shuffle_id
+            ),
         }
     }
 
+    pub fn unregister_shuffle(&self, shuffle_id: usize) {
+        info!(
+            "unregistering shuffle {} from map output tracker",
+            shuffle_id
+        );
+        self.map_statuses.write().remove(&shuffle_id);
+        self.fetching.write().remove(&shuffle_id);
+    }
+
     pub fn get_server_uris(&self, shuffle_id: usize) -> Vec<String> {
+        self.get_map_statuses(shuffle_id)
+            .into_iter()
+            .map(|status| status.server_uri)
+            .collect()
+    }
+
+    /// Returns the estimated size of every reduce partition of the shuffle, summed over all map outputs.
+    pub fn get_statistics(&self, shuffle_id: usize) -> MapOutputStatistics {
+        // the master knows the statuses once the map stage finished, without asking its own server
+        let registered = self
+            .map_statuses
+            .read()
+            .get(&shuffle_id)
+            .and_then(|locs| locs.iter().cloned().collect::<Option<Vec<_>>>());
+        let statuses = match registered {
+            Some(statuses) => statuses,
+            None => self.get_map_statuses(shuffle_id),
+        };
+        let num_partitions = statuses
+            .get(0)
+            .map_or(0, |status| status.compressed_sizes.len());
+        let mut bytes_by_partition = vec![0; num_partitions];
+        let mut records_by_partition = vec![0; num_partitions];
+        for status in &statuses {
+            for reduce_id in 0..num_partitions {
+                bytes_by_partition[reduce_id] += status.get_size_for_block(reduce_id);
+                records_by_partition[reduce_id] += status.get_record_count_for_block(reduce_id);
+            }
+        }
+        MapOutputStatistics {
+            shuffle_id,
+            bytes_by_partition,
+            records_by_partition,
+        }
+    }
+
+    pub fn get_map_statuses(&self, shuffle_id: usize) -> Vec<MapStatus> {
         info!(
-            "server uris inside get_server_uris method {:?}",
-            self.server_uris
+            "map statuses inside get_map_statuses method {:?}",
+            self.map_statuses
         );
         //        let locs = self.server_uris.read();
         //        let log_output = format!(
@@ -295,7 +430,7 @@ impl MapOutputTracker {
             }
             info!(
                 "returning after fetching done {:?}",
-                self.server_uris
+                self.map_statuses
                     .read()
                     .get(&shuffle_id)
                     .unwrap()
@@ -305,7 +440,7 @@ impl MapOutputTracker {
                     .collect::<Vec<_>>()
             );
             return self
-                .server_uris
+                .map_statuses
                 .read()
                 .get(&shuffle_id)
                 .unwrap()
@@ -319,11 +454,11 @@ impl MapOutputTracker {
         // TODO logging
         let fetched = self.client(shuffle_id);
         info!("fetched locs from client {:?}", fetched);
-        self.server_uris.write().insert(
+        self.map_statuses.write().insert(
             shuffle_id,
             fetched.iter().map(|x| Some(x.clone())).collect(),
         );
-        info!("wriiten to server_uris after fetching");
+        info!("wriiten to map_statuses after fetching");
         self.fetching.write().remove(&shuffle_id);
         info!("returning from get server uri");
 
@@ -354,8 +489,49 @@ impl MapOutputTracker {
 
     pub fn update_generation(&mut self, new_gen: i64) {
         if new_gen > *self.generation.lock() {
-            self.server_uris = Arc::new(RwLock::new(HashMap::new()));
+            self.map_statuses = Arc::new(RwLock::new(HashMap::new()));
             *self.generation.lock() = new_gen;
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compressed_sizes_are_close() {
+        assert_eq!(decompress_size(compress_size(0)), 0);
+        assert_eq!(decompress_size(compress_size(1)), 1);
+        for &size in &[2u64, 100, 1000, 123_456, 1 << 30] {
+            let estimate = decompress_size(compress_size(size));
+            assert!(estimate >= size, "{} estimated as {}", size, estimate);
+            assert!(
+                estimate as f64 <= size as f64 * 1.1 + 1.0,
+                "{} estimated as {}",
+                size,
+                estimate
+            );
+        }
+        assert_eq!(compress_size(std::u64::MAX), 255);
+    }
+
+    #[test]
+    fn small_partitions_are_coalesced() {
+        assert_eq!(
+            coalesce_partitions(&[10, 20, 30, 100, 5, 5, 0], 60),
+            vec![(0, 3), (3, 4), (4, 7)]
+        );
+        assert_eq!(coalesce_partitions(&[0, 0, 0], 60), vec![(0, 3)]);
+        assert!(coalesce_partitions(&[], 60).is_empty());
+    }
+
+    #[test]
+    fn map_status_reports_block_sizes() {
+        let status = MapStatus::new("http://host:5000".to_string(), &[0, 10, 5000], &[0, 1, 200]);
+        assert_eq!(status.get_size_for_block(0), 0);
+        assert_eq!(status.get_record_count_for_block(0), 0);
+        assert_eq!(status.get_record_count_for_block(1), 1);
+        assert!(status.get_size_for_block(2) >= 5000);
+    }
+}
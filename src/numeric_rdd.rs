@@ -0,0 +1,191 @@
+use super::*;
+use std::iter;
+
+// Element types which can be summarized by NumericRdd. Everything is converted to f64 before aggregation, like in Spark's DoubleRDDFunctions.
+pub trait Numeric: Data + Copy {
+    fn to_f64(self) -> f64;
+}
+
+macro_rules! impl_numeric {
+    ($($t:ty),*) => {
+        $(
+            impl Numeric for $t {
+                fn to_f64(self) -> f64 {
+                    self as f64
+                }
+            }
+        )*
+    };
+}
+
+impl_numeric!(f32, f64, i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+// Counts the elements of a partition falling into each bucket. The last bucket is closed on the right, all others are half open.
+fn histogram_partition<T: Numeric>(
+    iter: Box<dyn Iterator<Item = T>>,
+    buckets: &[f64],
+    evenly_spaced: bool,
+) -> Vec<usize> {
+    let num_buckets = buckets.len() - 1;
+    let min = buckets[0];
+    let max = buckets[num_buckets];
+    let mut counts = vec![0; num_buckets];
+    for value in iter {
+        let value = value.to_f64();
+        if value.is_nan() || value < min || value > max {
+            continue;
+        }
+        // a single bucket holding one value has no width to divide by
+        let bucket = if evenly_spaced && max > min {
+            // Since the buckets are evenly spaced, the bucket can be computed directly instead of searching for it.
+            let bucket = ((value - min) / (max - min) * num_buckets as f64) as usize;
+            bucket.min(num_buckets - 1)
+        } else {
+            match buckets.binary_search_by(|b| b.partial_cmp(&value).unwrap()) {
+                Ok(index) => index.min(num_buckets - 1),
+                Err(index) => index - 1,
+            }
+        };
+        counts[bucket] += 1;
+    }
+    counts
+}
+
+// Extra functions available on RDDs of numbers. Like PairRdd, it is implemented for all RDDs with suitable element type.
+pub trait NumericRdd<T: Numeric>: Rdd<T> + Send + Sync {
+    /// Return a StatCounter object that captures the mean, variance and count of the RDD's elements in one operation.
+    fn stats(&self) -> Result<StatCounter>
+    where
+        Self: Sized + 'static,
+    {
+        let stat_partition = Fn!(|iter: Box<dyn Iterator<Item = T>>| {
+            StatCounter::from_values(iter.map(|x| x.to_f64()))
+        });
+        let results = self.get_context().run_job(self.get_rdd(), stat_partition)?;
+        Ok(results
+            .into_iter()
+            .fold(StatCounter::new(), |mut acc, stats| {
+                acc.merge_stats(&stats);
+                acc
+            }))
+    }
+
+    /// Add up the elements in this RDD.
+    fn sum(&self) -> Result<f64>
+    where
+        Self: Sized + 'static,
+    {
+        Ok(self.stats()?.sum())
+    }
+
+    /// Compute the mean of this RDD's elements.
+    fn mean(&self) -> Result<f64>
+    where
+        Self: Sized + 'static,
+    {
+        Ok(self.stats()?.mean())
+    }
+
+    /// Compute the population variance of this RDD's elements.
+    fn variance(&self) -> Result<f64>
+    where
+        Self: Sized + 'static,
+    {
+        Ok(self.stats()?.variance())
+    }
+
+    /// Compute the sample variance of this RDD's elements (which corrects for bias in estimating the variance by dividing by N-1 instead of N).
+    fn sample_variance(&self) -> Result<f64>
+    where
+        Self: Sized + 'static,
+    {
+        Ok(self.stats()?.sample_variance())
+    }
+
+    /// Compute the population standard deviation of this RDD's elements.
+    fn stdev(&self) -> Result<f64>
+    where
+        Self: Sized + 'static,
+    {
+        Ok(self.stats()?.stdev())
+    }
+
+    /// Compute the sample standard deviation of this RDD's elements (which corrects for bias in estimating the standard deviation by dividing by N-1 instead of N).
+    fn sample_stdev(&self) -> Result<f64>
+    where
+        Self: Sized + 'static,
+    {
+        Ok(self.stats()?.sample_stdev())
+    }
+
+    /// Compute a histogram of the data using `bucket_count` number of buckets evenly spaced between the minimum and maximum of the RDD.
+    /// For example if the min value is 0 and the max is 100 and there are two buckets the resulting buckets will be [0, 50) [50, 100].
+    /// Returns the bucket boundaries together with the counts. If the RDD contains only one distinct value, a single bucket is returned.
+    fn histogram(&self, bucket_count: usize) -> Result<(Vec<f64>, Vec<usize>)>
+    where
+        Self: Sized + 'static,
+    {
+        if bucket_count == 0 {
+            return Err(Error::UnsupportedOperation(
+                "histogram with a bucket_count of zero",
+            ));
+        }
+        let stats = self.stats()?;
+        let (min, max) = (stats.min(), stats.max());
+        if min.is_nan() || max.is_nan() || min.is_infinite() || max.is_infinite() {
+            return Err(Error::UnsupportedOperation(
+                "histogram on either an empty RDD or RDD containing +/-infinity or NaN",
+            ));
+        }
+        let buckets: Vec<f64> = if min == max {
+            vec![min, max]
+        } else {
+            (0..bucket_count)
+                .map(|i| min + (i as f64 * (max - min)) / bucket_count as f64)
+                .chain(iter::once(max))
+                .collect()
+        };
+        // a constant RDD has the single bucket [min, max], found by search
+        let counts = self.histogram_with_buckets(buckets.clone(), min != max)?;
+        Ok((buckets, counts))
+    }
+
+    /// Compute a histogram using the provided buckets. The buckets are all open to the right except for the last which is closed.
+    /// For example for the buckets [1, 10, 20, 50] the resulting buckets are [1, 10) [10, 20) [20, 50].
+    /// Elements outside of the buckets are ignored. If `evenly_spaced` is set, the bucket of an element is computed directly
+    /// instead of doing a binary search. Fails if there are less than two boundaries or they are not strictly increasing.
+    fn histogram_with_buckets(&self, buckets: Vec<f64>, evenly_spaced: bool) -> Result<Vec<usize>>
+    where
+        Self: Sized + 'static,
+    {
+        if buckets.len() < 2 {
+            return Err(Error::UnsupportedOperation(
+                "histogram with less than two bucket boundaries",
+            ));
+        }
+        // comparisons with NaN are false, so NaN boundaries are rejected along with unsorted ones
+        let is_sorted = buckets.windows(2).all(|w| w[0] < w[1])
+            || (buckets.len() == 2 && buckets[0] == buckets[1]);
+        if !is_sorted {
+            return Err(Error::UnsupportedOperation(
+                "histogram with buckets which are not sorted, contain duplicates or NaN",
+            ));
+        }
+        let num_buckets = buckets.len() - 1;
+        let histogram = Fn!([buckets, evenly_spaced] move |iter: Box<dyn Iterator<Item = T>>| {
+            histogram_partition(iter, buckets, *evenly_spaced)
+        });
+        let results = self.get_context().run_job(self.get_rdd(), histogram)?;
+        Ok(results
+            .into_iter()
+            .fold(vec![0; num_buckets], |mut acc, counts| {
+                for (total, count) in acc.iter_mut().zip(counts) {
+                    *total += count;
+                }
+                acc
+            }))
+    }
+}
+
+// Implementing the NumericRdd trait for all types which implements the Rdd trait for numeric elements
+impl<T: Numeric, R> NumericRdd<T> for R where R: Rdd<T> {}
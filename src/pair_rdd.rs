@@ -1,4 +1,6 @@
 use super::*;
+use std::any::Any;
+use std::collections::{HashMap, HashSet};
 use std::hash::Hash;
 use std::marker::PhantomData;
 use std::sync::Arc;
@@ -24,6 +26,7 @@ pub trait PairRdd<K: Data + Eq + Hash, V: Data>: Rdd<(K, V)> + Send + Sync {
         merge_value: Box<dyn serde_traitobject::Fn((C, V)) -> C + Send + Sync>,
         merge_combiners: Box<dyn serde_traitobject::Fn((C, C)) -> C + Send + Sync>,
         partitioner: Box<dyn Partitioner>,
+        map_side_combine: bool,
         //    ) -> Arc<RddBox<(K, C)>>
     ) -> ShuffledRdd<K, V, C, Self>
     where
@@ -35,7 +38,7 @@ pub trait PairRdd<K: Data + Eq + Hash, V: Data>: Rdd<(K, V)> + Send + Sync {
             merge_value,
             merge_combiners,
         ));
-        ShuffledRdd::new(self.get_rdd(), aggregator, partitioner)
+        ShuffledRdd::new(self.get_rdd(), aggregator, partitioner, map_side_combine)
     }
 
     fn group_by_key(&self, num_splits: usize) -> ShuffledRdd<K, V, Vec<V>, Self>
@@ -67,7 +70,14 @@ pub trait PairRdd<K: Data + Eq + Hash, V: Data>: Rdd<(K, V)> + Send + Sync {
             //            b1
         }
         let merge_combiners = Box::new(Fn!(|(b1, b2)| merge_combiners::<V>(b1, b2)));
-        self.combine_by_key(create_combiner, merge_value, merge_combiners, partitioner)
+        // Grouping doesn't reduce the amount of data, so there is no point in combining on the map side.
+        self.combine_by_key(
+            create_combiner,
+            merge_value,
+            merge_combiners,
+            partitioner,
+            false,
+        )
         //        unimplemented!()
     }
     fn reduce_by_key<F>(&self, func: F, num_splits: usize) -> ShuffledRdd<K, V, V, Self>
@@ -113,7 +123,93 @@ pub trait PairRdd<K: Data + Eq + Hash, V: Data>: Rdd<(K, V)> + Send + Sync {
         let merge_combiners = Box::new(
             Fn!([func_clone] move | (b1, b2) | merge_combiners::<V, F>(b1, b2, func_clone.clone())),
         );
-        self.combine_by_key(create_combiner, merge_value, merge_combiners, partitioner)
+        self.combine_by_key(
+            create_combiner,
+            merge_value,
+            merge_combiners,
+            partitioner,
+            true,
+        )
+    }
+
+    /// Aggregate the values of each key, using given combine functions and a neutral "zero value".
+    /// The result type U can be different from the value type V. `seq_op` merges a value into an
+    /// aggregate within a partition and `comb_op` merges aggregates from different partitions.
+    fn aggregate_by_key<U: Data, F1, F2>(
+        &self,
+        zero_value: U,
+        seq_op: F1,
+        comb_op: F2,
+        num_splits: usize,
+    ) -> ShuffledRdd<K, V, U, Self>
+    where
+        F1: SerFunc((U, V)) -> U,
+        F2: SerFunc((U, U)) -> U,
+        Self: Sized + Serialize + Deserialize + 'static,
+    {
+        self.aggregate_by_key_using_partitioner(
+            zero_value,
+            seq_op,
+            comb_op,
+            Box::new(HashPartitioner::<K>::new(num_splits)) as Box<dyn Partitioner>,
+        )
+    }
+
+    fn aggregate_by_key_using_partitioner<U: Data, F1, F2>(
+        &self,
+        zero_value: U,
+        seq_op: F1,
+        comb_op: F2,
+        partitioner: Box<dyn Partitioner>,
+    ) -> ShuffledRdd<K, V, U, Self>
+    where
+        F1: SerFunc((U, V)) -> U,
+        F2: SerFunc((U, U)) -> U,
+        Self: Sized + Serialize + Deserialize + 'static,
+    {
+        // zero value is cloned for every key, so that combiners never share state.
+        let seq_op_clone = seq_op.clone();
+        let create_combiner = Box::new(
+            Fn!([zero_value, seq_op_clone] move |v: V| seq_op_clone((zero_value.clone(), v))),
+        );
+        let merge_value = Box::new(Fn!([seq_op] move |(u, v): (U, V)| seq_op((u, v))));
+        let merge_combiners = Box::new(Fn!([comb_op] move |(u1, u2): (U, U)| comb_op((u1, u2))));
+        self.combine_by_key(
+            create_combiner,
+            merge_value,
+            merge_combiners,
+            partitioner,
+            true,
+        )
+    }
+
+    /// Merge the values for each key using an associative function and a neutral "zero value"
+    /// which may be added to the result an arbitrary number of times.
+    fn fold_by_key<F>(
+        &self,
+        zero_value: V,
+        func: F,
+        num_splits: usize,
+    ) -> ShuffledRdd<K, V, V, Self>
+    where
+        F: SerFunc((V, V)) -> V,
+        Self: Sized + Serialize + Deserialize + 'static,
+    {
+        self.aggregate_by_key(zero_value, func.clone(), func, num_splits)
+    }
+
+    /// Return a copy of the RDD partitioned using the given partitioner, e.g. a `FnPartitioner`.
+    /// The resulting RDD keeps the partitioner, so later joins and cogroups with the same partitioner avoid a shuffle.
+    fn partition_by(
+        &self,
+        partitioner: Box<dyn Partitioner>,
+    ) -> FlatMappedValuesRdd<ShuffledRdd<K, V, Vec<V>, Self>, K, Vec<V>, V>
+    where
+        Self: Sized + Serialize + Deserialize + 'static,
+    {
+        let f = Fn!(|v: Vec<V>| Box::new(v.into_iter()) as Box<dyn Iterator<Item = V>>);
+        self.group_by_key_using_partitioner(partitioner)
+            .flat_map_values(Arc::new(f))
     }
 
     fn map_values<U: Data>(&self, f: Arc<dyn Func(V) -> U>) -> MappedValuesRdd<Self, K, V, U>
@@ -157,6 +253,66 @@ pub trait PairRdd<K: Data + Eq + Hash, V: Data>: Rdd<(K, V)> + Send + Sync {
         .flat_map_values(Arc::new(f))
     }
 
+    /// Inner join which spreads the rows of hot keys over several reduce tasks. Keys with more than
+    /// `hot_key_threshold` rows in this RDD are split into `salt_factor` parts, and the matching rows of
+    /// `other` are replicated to each of them. The other keys are joined as usual. Finding the hot keys
+    /// runs a job counting the rows per key.
+    fn skew_join<W: Data, RT: Rdd<(K, W)>>(
+        &self,
+        other: RT,
+        num_splits: usize,
+        hot_key_threshold: u64,
+        salt_factor: usize,
+    ) -> Result<SkewJoinedRdd<K, V, W>>
+    where
+        Self: Sized + 'static,
+        RT: Sized + 'static,
+    {
+        let hot_keys = self
+            .map(Fn!(|(k, _v): (K, V)| (k, 1u64)))
+            .reduce_by_key(Fn!(|(a, b): (u64, u64)| a + b), num_splits)
+            .filter(Fn!([hot_key_threshold] move |(_k, count): &(K, u64)| {
+                *count > *hot_key_threshold
+            }))
+            .collect()?
+            .into_iter()
+            .map(|(k, _count)| k)
+            .collect::<HashSet<K>>();
+        info!("skew join splits {} hot keys", hot_keys.len());
+        let salt_factor = salt_factor.max(1);
+        // the rows of each hot key get consecutive salts, counted per key within the partition, so that
+        // recomputed partitions are salted the same way
+        let salted = {
+            let hot_keys = hot_keys.clone();
+            self.map_partitions(
+                Fn!([hot_keys, salt_factor] move |iter: Box<dyn Iterator<Item = (K, V)>>| {
+                    let hot_keys = hot_keys.clone();
+                    let salt_factor = *salt_factor;
+                    let mut rows = HashMap::new();
+                    Box::new(iter.map(move |(k, v)| {
+                        let salt = if hot_keys.contains(&k) {
+                            let row = rows.entry(k.clone()).or_insert(0);
+                            *row += 1;
+                            (*row - 1) % salt_factor
+                        } else {
+                            0
+                        };
+                        ((k, salt), v)
+                    })) as Box<dyn Iterator<Item = ((K, usize), V)>>
+                }),
+                false,
+            )
+        };
+        let replicated = other.flat_map(Fn!([hot_keys, salt_factor] move |(k, w): (K, W)| {
+            let salts = if hot_keys.contains(&k) { *salt_factor } else { 1 };
+            Box::new((0..salts).map(move |salt| ((k.clone(), salt), w.clone())))
+                as Box<dyn Iterator<Item = ((K, usize), W)>>
+        }));
+        let unsalt: SerBox<dyn Func(((K, usize), (V, W))) -> (K, (V, W))> =
+            SerBox::new(Fn!(|((k, _salt), vw): ((K, usize), (V, W))| (k, vw)));
+        Ok(salted.join(replicated, num_splits).map(unsalt))
+    }
+
     fn cogroup<W: Data, RT: Rdd<(K, W)>>(
         &self,
         other: RT,
@@ -190,8 +346,61 @@ pub trait PairRdd<K: Data + Eq + Hash, V: Data>: Rdd<(K, V)> + Send + Sync {
         });
         cg_rdd.map_values(Arc::new(f))
     }
+
+    /// Count the number of elements for each key, and return the result to the driver as a map.
+    fn count_by_key(&self) -> Result<HashMap<K, u64>>
+    where
+        Self: Sized + 'static,
+    {
+        self.map(Fn!(|(k, _v): (K, V)| k)).count_by_value()
+    }
+
+    /// Return the list of values in the RDD for the given key. If the RDD has a known partitioner,
+    /// only the partition the key maps to is scanned.
+    fn lookup(&self, key: K) -> Result<Vec<V>>
+    where
+        Self: Sized + 'static,
+    {
+        let partition = self
+            .partitioner()
+            .map(|partitioner| partitioner.get_partition(&key as &dyn Any));
+        let lookup_partition = Fn!([key] move |iter: Box<dyn Iterator<Item = (K, V)>>| {
+            iter.filter(|(k, _)| k == key)
+                .map(|(_, v)| v)
+                .collect::<Vec<V>>()
+        });
+        let results = match partition {
+            Some(index) => self.get_context().run_job_on_partitions(
+                self.get_rdd(),
+                lookup_partition,
+                vec![index],
+            )?,
+            None => self
+                .get_context()
+                .run_job(self.get_rdd(), lookup_partition)?,
+        };
+        Ok(results.into_iter().flatten().collect())
+    }
 }
 
+// Result of PairRdd::skew_join, a join on keys salted with a usize which is removed afterwards.
+pub type SkewJoinedRdd<K, V, W> = MapperRdd<
+    FlatMappedValuesRdd<
+        MappedValuesRdd<
+            CoGroupedRdd<(K, usize)>,
+            (K, usize),
+            Vec<Vec<Box<dyn AnyData>>>,
+            (Vec<V>, Vec<W>),
+        >,
+        (K, usize),
+        (Vec<V>, Vec<W>),
+        (V, W),
+    >,
+    ((K, usize), (V, W)),
+    (K, (V, W)),
+    SerBox<dyn Func(((K, usize), (V, W))) -> (K, (V, W))>,
+>;
+
 // Implementing the PairRdd trait for all types which implements Rdd
 impl<K: Data + Eq + Hash, V: Data, T> PairRdd<K, V> for T where T: Rdd<(K, V)> {}
 
@@ -268,8 +477,22 @@ where
     fn number_of_splits(&self) -> usize {
         self.prev.number_of_splits()
     }
+    // Values are transformed without touching the keys, so the parent's partitioner still holds.
+    fn partitioner(&self) -> Option<Box<dyn Partitioner>> {
+        self.prev.partitioner()
+    }
     fn iterator_any(&self, split: Box<dyn Split>) -> Box<dyn Iterator<Item = Box<dyn AnyData>>> {
         info!("inside iterator_any mapvaluesrdd",);
+        Box::new(
+            self.iterator(split)
+                .map(|(k, v)| Box::new((k, v)) as Box<dyn AnyData>),
+        )
+    }
+    fn cogroup_iterator_any(
+        &self,
+        split: Box<dyn Split>,
+    ) -> Box<dyn Iterator<Item = Box<dyn AnyData>>> {
+        info!("inside cogroup iterator_any mapvaluesrdd",);
         Box::new(
             self.iterator(split)
                 .map(|(k, v)| Box::new((k, Box::new(v) as Box<dyn AnyData>)) as Box<dyn AnyData>),
@@ -370,6 +593,10 @@ where
     fn number_of_splits(&self) -> usize {
         self.prev.number_of_splits()
     }
+    // Values are transformed without touching the keys, so the parent's partitioner still holds.
+    fn partitioner(&self) -> Option<Box<dyn Partitioner>> {
+        self.prev.partitioner()
+    }
     fn iterator_any(&self, split: Box<dyn Split>) -> Box<dyn Iterator<Item = Box<dyn AnyData>>> {
         info!("inside iterator_any flatmapvaluesrdd",);
         Box::new(
@@ -1,6 +1,7 @@
 use super::*;
 //use std::hash::Hash;
 use std::hash::Hash;
+use std::net::Ipv4Addr;
 use std::sync::Arc;
 //use std::any::Any;
 
@@ -48,6 +49,8 @@ pub struct ParallelCollectionVals<T> {
     //    data: Option<Vec<T>>,
     splits_: Vec<Arc<Vec<T>>>,
     num_slices: usize,
+    // hosts each split prefers to be computed on, empty if it doesn't matter
+    locations: Vec<Vec<Ipv4Addr>>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -71,6 +74,25 @@ impl<T: Data> ParallelCollection<T> {
                 context,
                 splits_: ParallelCollection::slice(data, num_slices),
                 num_slices,
+                locations: Vec::new(),
+            }),
+        }
+    }
+
+    // One split per element, computed on one of the hosts given along with it.
+    pub fn with_locations(context: Context, data: Vec<(T, Vec<Ipv4Addr>)>) -> Self {
+        let num_slices = data.len();
+        let (splits_, locations) = data
+            .into_iter()
+            .map(|(value, hosts)| (Arc::new(vec![value]), hosts))
+            .unzip();
+        ParallelCollection {
+            rdd_vals: Arc::new(ParallelCollectionVals {
+                vals: Arc::new(RddVals::new(context.clone())),
+                context,
+                splits_,
+                num_slices,
+                locations,
             }),
         }
     }
@@ -143,6 +165,13 @@ impl<T: Data> RddBase for ParallelCollection<T> {
     fn number_of_splits(&self) -> usize {
         self.rdd_vals.splits_.len()
     }
+    fn preferred_locations(&self, split: Box<dyn Split>) -> Vec<Ipv4Addr> {
+        self.rdd_vals
+            .locations
+            .get(split.get_index())
+            .cloned()
+            .unwrap_or_default()
+    }
 
     default fn cogroup_iterator_any(
         &self,
@@ -1,9 +1,11 @@
 use super::*;
+use downcast_rs::Downcast;
 use fasthash::MetroHasher;
 //use objekt;
 use std::any::Any;
 use std::hash::{Hash, Hasher};
 use std::marker::PhantomData;
+use std::sync::Arc;
 
 // Partitioner trait for creating Rdd partitions
 //pub trait Partitioner: objekt::Clone + Send + Sync  {
@@ -12,14 +14,16 @@ use std::marker::PhantomData;
 //    fn get_num_of_partitions(&self) -> usize;
 //}
 
-pub trait Partitioner: Send + Sync + objekt::Clone + Serialize + Deserialize {
+// `equals` receives the other partitioner as `&dyn Any`; use `as_any` on a boxed partitioner to pass the concrete type.
+pub trait Partitioner: Downcast + Send + Sync + objekt::Clone + Serialize + Deserialize {
     fn equals(&self, other: &dyn Any) -> bool;
     fn get_num_of_partitions(&self) -> usize;
     fn get_partition(&self, key: &dyn Any) -> usize;
 }
+impl_downcast!(Partitioner);
 objekt::clone_trait_object!(Partitioner);
 
-fn hash<T: Hash>(t: &T) -> u64 {
+pub(crate) fn hash<T: Hash>(t: &T) -> u64 {
     let mut s: MetroHasher = Default::default();
     t.hash(&mut s);
     s.finish()
@@ -76,6 +80,52 @@ impl<K: Data + Hash + Eq> Partitioner for HashPartitioner<K> {
     }
 }
 
+// Partitioner built from a serializable closure, for custom partitioning without handling `&dyn Any` keys.
+// The tag identifies the partitioning scheme: two FnPartitioners are considered equal when both the tag and
+// the number of partitions match, since closures themselves cannot be compared.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct FnPartitioner<K: Data> {
+    partitions: usize,
+    tag: String,
+    #[serde(with = "serde_traitobject")]
+    func: Arc<dyn Func(&K) -> usize>,
+}
+
+impl<K: Data> FnPartitioner<K> {
+    pub fn new<F>(partitions: usize, tag: &str, func: F) -> Self
+    where
+        F: SerFunc(&K) -> usize,
+    {
+        FnPartitioner {
+            partitions,
+            tag: tag.to_string(),
+            func: Arc::new(func) as Arc<dyn Func(&K) -> usize>,
+        }
+    }
+
+    pub fn tag(&self) -> &str {
+        &self.tag
+    }
+}
+
+impl<K: Data> Partitioner for FnPartitioner<K> {
+    fn equals(&self, other: &dyn Any) -> bool {
+        if let Some(fp) = other.downcast_ref::<FnPartitioner<K>>() {
+            self.partitions == fp.partitions && self.tag == fp.tag
+        } else {
+            false
+        }
+    }
+    fn get_num_of_partitions(&self) -> usize {
+        self.partitions
+    }
+    fn get_partition(&self, key: &dyn Any) -> usize {
+        let key = key.downcast_ref::<K>().unwrap();
+        // Out of range results are wrapped around, so a closure can't produce an invalid partition.
+        (self.func)(key) % self.partitions
+    }
+}
+
 //impl PartialEq<Any> for HashPartitioner {
 //    fn eq(&self, other: &Any) -> bool {
 //        if let Some(hp) = other.downcast_ref::<HashPartitioner>() {
@@ -145,4 +195,29 @@ mod tests {
         assert!(!p1.clone().map_or(false, |p| p.equals(&p2_1.clone())));
         assert!(!p1.clone().map_or(false, |p| p.equals(&p2_2.clone())));
     }
+
+    #[test]
+    fn fn_partitioner() {
+        let p = FnPartitioner::<i32>::new(3, "mod", Fn!(|k: &i32| *k as usize));
+        assert_eq!(p.get_partition(&1), 1);
+        assert_eq!(p.get_partition(&5), 2);
+        assert_eq!(p.get_num_of_partitions(), 3);
+    }
+
+    #[test]
+    fn fn_partitioner_eq() {
+        let p1 = FnPartitioner::<i32>::new(3, "mod", Fn!(|k: &i32| *k as usize));
+        let p2 = FnPartitioner::<i32>::new(3, "mod", Fn!(|k: &i32| *k as usize + 1));
+        let p3 = FnPartitioner::<i32>::new(3, "other", Fn!(|k: &i32| *k as usize));
+        let p4 = FnPartitioner::<i32>::new(4, "mod", Fn!(|k: &i32| *k as usize));
+        let hp = HashPartitioner::<i32>::new(3);
+        assert!(p1.equals(&p1.clone()));
+        assert!(p1.equals(&p2));
+        assert!(!p1.equals(&p3));
+        assert!(!p1.equals(&p4));
+        assert!(!p1.equals(&hp));
+        assert!(!hp.equals(&p1));
+        let boxed = Box::new(p2) as Box<dyn Partitioner>;
+        assert!(p1.equals((*boxed).as_any()));
+    }
 }
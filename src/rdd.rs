@@ -3,6 +3,7 @@ use std::path::Path;
 //use objekt::Clone;
 //use chrono::format::Item;
 use std::cmp::Ordering;
+use std::collections::HashMap;
 use std::fs;
 use std::hash::Hash;
 use std::io::{BufWriter, Write};
@@ -40,6 +41,9 @@ pub struct RddVals {
     should_cache: bool,
     #[serde(skip_serializing, skip_deserializing)]
     pub context: Context,
+    // Only set on the master, copies deserialized inside tasks never trigger a cleanup.
+    #[serde(skip_serializing, skip_deserializing)]
+    shuffle_cleanup: Option<ShuffleCleanup>,
 }
 
 impl RddVals {
@@ -49,7 +53,28 @@ impl RddVals {
             dependencies: Vec::new(),
             should_cache: false,
             context: sc.clone(),
+            shuffle_cleanup: None,
+        }
+    }
+
+    // Deletes the outputs of the shuffle dependencies once these vals are dropped. Should be called by rdds
+    // creating shuffle dependencies, after the dependencies are added.
+    pub(crate) fn track_shuffles(mut self) -> Self {
+        let shuffle_ids = self
+            .dependencies
+            .iter()
+            .filter_map(|dep| match dep {
+                Dependency::ShuffleDependency(shuf_dep) => Some(shuf_dep.get_shuffle_id()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        if !shuffle_ids.is_empty() {
+            self.shuffle_cleanup = Some(ShuffleCleanup {
+                shuffle_ids,
+                context: self.context.clone(),
+            });
         }
+        self
     }
 
     fn cache(mut self) -> Self {
@@ -58,6 +83,21 @@ impl RddVals {
     }
 }
 
+// Dropping it cleans up the shuffles, which happens when the last rdd depending on them is dropped.
+// The scheduler holds rdds of stages which depend on other shuffles, so the cleanup is only queued here and
+// runs on the cleaner thread of the context, to avoid dropping rdds while the scheduler is locked.
+struct ShuffleCleanup {
+    shuffle_ids: Vec<usize>,
+    context: Context,
+}
+
+impl Drop for ShuffleCleanup {
+    fn drop(&mut self) {
+        let shuffle_ids = std::mem::replace(&mut self.shuffle_ids, Vec::new());
+        self.context.queue_shuffle_cleanup(shuffle_ids);
+    }
+}
+
 // Due to the lack of HKTs in Rust, it is difficult to have collection of generic data with different types.
 // Required for storing multiple RDDs inside dependencies and other places like Tasks, etc.,
 // Refactored RDD trait into two traits one having RddBase trait which contains only non generic methods which provide information for dependency lists
@@ -84,6 +124,11 @@ pub trait RddBase: Send + Sync + Serialize + Deserialize {
     ) -> Box<dyn Iterator<Item = Box<dyn AnyData>>> {
         self.iterator_any(split)
     }
+    // Split reading the reduce partitions start..end of the rdd's shuffle as one partition. Used by the
+    // scheduler to coalesce small partitions in the tasks of a stage, None if the rdd doesn't support it.
+    fn coalesced_split(&self, index: usize, start: usize, end: usize) -> Option<Box<dyn Split>> {
+        None
+    }
 }
 
 //pub trait RddBaseBox: RddBase + Serialize + Deserialize {}
@@ -141,7 +186,31 @@ pub trait Rdd<T: Data>: RddBase + Send + Sync + Serialize + Deserialize {
         FlatMapperRdd::new(self.get_rdd(), f)
     }
 
-    fn save_as_text_file(&self, path: String)
+    /// Return a new RDD containing only the elements that satisfy the predicate.
+    fn filter<F>(&self, predicate: F) -> FilterRdd<Self, T, F>
+    where
+        F: SerFunc(&T) -> bool,
+        Self: Sized + 'static,
+    {
+        FilterRdd::new(self.get_rdd(), predicate)
+    }
+
+    /// Return a new RDD by applying a function to each partition of this RDD.
+    /// `preserves_partitioning` indicates whether the function keeps the keys of a pair RDD unchanged,
+    /// in which case the parent's partitioner is kept and later joins can avoid a shuffle.
+    fn map_partitions<U: Data, F>(
+        &self,
+        f: F,
+        preserves_partitioning: bool,
+    ) -> MapPartitionsRdd<Self, T, U, F>
+    where
+        F: SerFunc(Box<dyn Iterator<Item = T>>) -> Box<dyn Iterator<Item = U>>,
+        Self: Sized + 'static,
+    {
+        MapPartitionsRdd::new(self.get_rdd(), f, preserves_partitioning)
+    }
+
+    fn save_as_text_file(&self, path: String) -> Result<()>
     where
         Self: Sized + 'static,
     {
@@ -159,10 +228,12 @@ pub trait Rdd<T: Data>: RddBase + Send + Sync + Serialize + Deserialize {
             }
         }
         let cl = Fn!([path] move |(ctx, iter)| save::<T>(ctx, iter, path.to_string()));
-        self.get_context().run_job_with_context(self.get_rdd(), cl);
+        self.get_context()
+            .run_job_with_context(self.get_rdd(), cl)?;
+        Ok(())
     }
 
-    fn reduce<F>(&self, f: F) -> Option<T>
+    fn reduce<F>(&self, f: F) -> Result<Option<T>>
     where
         Self: Sized + 'static,
         F: SerFunc(T, T) -> T,
@@ -177,36 +248,101 @@ pub trait Rdd<T: Data>: RddBase + Send + Sync + Serialize + Deserialize {
         }
 
         });
-        let results = self.get_context().run_job(self.get_rdd(), reduce_partition);
-        results.into_iter().flatten().reduce(f)
+        let results = self
+            .get_context()
+            .run_job(self.get_rdd(), reduce_partition)?;
+        Ok(results.into_iter().flatten().reduce(f))
     }
 
-    fn collect(&self) -> Vec<T>
+    fn collect(&self) -> Result<Vec<T>>
     where
         Self: Sized + 'static,
     {
         let cl = Fn!(|iter: Box<dyn Iterator<Item = T>>| iter.collect::<Vec<T>>());
-        let results = self.get_context().run_job(self.get_rdd(), cl);
+        let results = self.get_context().run_job(self.get_rdd(), cl)?;
         let size = results.iter().fold(0, |a, b: &Vec<T>| a + b.len());
-        results
+        Ok(results
             .into_iter()
             .fold(Vec::with_capacity(size), |mut acc, v| {
                 acc.extend(v);
                 acc
+            }))
+    }
+
+    /// Return the number of elements in the RDD.
+    fn count(&self) -> Result<u64>
+    where
+        Self: Sized + 'static,
+    {
+        let cl = Fn!(|iter: Box<dyn Iterator<Item = T>>| iter.count() as u64);
+        Ok(self
+            .get_context()
+            .run_job(self.get_rdd(), cl)?
+            .into_iter()
+            .sum())
+    }
+
+    /// Applies a function to all elements of this RDD.
+    fn for_each<F>(&self, f: F) -> Result<()>
+    where
+        F: SerFunc(T),
+        Self: Sized + 'static,
+    {
+        let cl = Fn!([f] move |iter: Box<dyn Iterator<Item = T>>| {
+            for item in iter {
+                (*f)(item);
+            }
+        });
+        self.get_context().run_job(self.get_rdd(), cl)?;
+        Ok(())
+    }
+
+    /// Starts collecting the RDD in the background, see JobHandle.
+    fn collect_async(&self) -> JobHandle<Vec<T>>
+    where
+        Self: Sized + 'static,
+    {
+        let cl = Fn!(|iter: Box<dyn Iterator<Item = T>>| iter.collect::<Vec<T>>());
+        self.get_context()
+            .submit_job_with(self.get_rdd(), cl, |results| {
+                results.into_iter().flatten().collect()
             })
     }
 
-    /// Return the first element in this RDD.
-    fn first(&self) -> Result<T, Box<dyn std::error::Error>>
+    /// Starts counting the elements of the RDD in the background, see JobHandle.
+    fn count_async(&self) -> JobHandle<u64>
+    where
+        Self: Sized + 'static,
+    {
+        let cl = Fn!(|iter: Box<dyn Iterator<Item = T>>| iter.count() as u64);
+        self.get_context()
+            .submit_job_with(self.get_rdd(), cl, |counts| counts.into_iter().sum())
+    }
+
+    /// Starts applying a function to all elements of this RDD in the background, see JobHandle.
+    fn for_each_async<F>(&self, f: F) -> JobHandle<()>
+    where
+        F: SerFunc(T),
+        Self: Sized + 'static,
+    {
+        let cl = Fn!([f] move |iter: Box<dyn Iterator<Item = T>>| {
+            for item in iter {
+                (*f)(item);
+            }
+        });
+        self.get_context()
+            .submit_job_with(self.get_rdd(), cl, |_| ())
+    }
+
+    /// Return the first element in this RDD, or an error if it is empty.
+    fn first(&self) -> Result<T>
     where
         Self: Sized + 'static + Serialize + Deserialize,
     {
-        if let Some(result) = self.take(1).into_iter().next() {
-            Ok(result)
-        } else {
-            //FIXME: return a proper error when we add return errors
-            panic!("empty collection")
-        }
+        self.take(1)?
+            .into_iter()
+            .next()
+            .ok_or(Error::UnsupportedOperation("empty collection"))
     }
 
     /// Take the first num elements of the RDD. It works by first scanning one partition, and use the
@@ -215,7 +351,7 @@ pub trait Rdd<T: Data>: RddBase + Send + Sync + Serialize + Deserialize {
     ///
     /// This method should only be used if the resulting array is expected to be small, as
     /// all the data is loaded into the driver's memory.
-    fn take(&self, num: usize) -> Vec<T>
+    fn take(&self, num: usize) -> Result<Vec<T>>
     where
         Self: 'static + Sized + Serialize + Deserialize,
     {
@@ -223,7 +359,7 @@ pub trait Rdd<T: Data>: RddBase + Send + Sync + Serialize + Deserialize {
         // Math.max(conf.get(RDD_LIMIT_SCALE_UP_FACTOR), 2)
         const scale_up_factor: f64 = 2.0;
         if num == 0 {
-            return vec![];
+            return Ok(vec![]);
         }
         let mut buf = vec![];
         let total_parts = self.number_of_splits() as u32;
@@ -259,7 +395,7 @@ pub trait Rdd<T: Data>: RddBase + Send + Sync + Serialize + Deserialize {
                 self.get_rdd(),
                 take_from_partion,
                 partitions,
-            );
+            )?;
 
             res.into_iter().for_each(|r| {
                 let take = num - buf.len();
@@ -269,7 +405,67 @@ pub trait Rdd<T: Data>: RddBase + Send + Sync + Serialize + Deserialize {
             parts_scanned += num_partitions;
         }
 
-        buf
+        Ok(buf)
+    }
+
+    /// Return the count of each unique value in this RDD as a map of (value, count) pairs.
+    /// Values are counted inside each partition first, so only one entry per distinct value and partition reaches the driver.
+    fn count_by_value(&self) -> Result<HashMap<T, u64>>
+    where
+        T: Eq + Hash,
+        Self: Sized + 'static,
+    {
+        let count_partition = Fn!(|iter: Box<dyn Iterator<Item = T>>| {
+            let mut counts = HashMap::new();
+            for item in iter {
+                *counts.entry(item).or_insert(0u64) += 1;
+            }
+            counts
+        });
+        let results = self
+            .get_context()
+            .run_job(self.get_rdd(), count_partition)?;
+        Ok(results
+            .into_iter()
+            .fold(HashMap::new(), |mut acc, counts: HashMap<T, u64>| {
+                for (item, count) in counts {
+                    *acc.entry(item).or_insert(0) += count;
+                }
+                acc
+            }))
+    }
+
+    /// Return the first num elements of this RDD as defined by the given comparator, in ascending order.
+    /// Each partition keeps only its num smallest elements in a bounded heap before sending them to the driver.
+    fn take_ordered<F>(&self, num: usize, cmp: F) -> Result<Vec<T>>
+    where
+        F: SerFunc(&T, &T) -> Ordering,
+        Self: Sized + 'static,
+    {
+        if num == 0 {
+            return Ok(vec![]);
+        }
+        let cf = cmp.clone();
+        let take_partition = Fn!([cf, num] move |iter: Box<dyn Iterator<Item = T>>| {
+            let mut queue = BoundedPriorityQueue::new(*num, cf.clone());
+            queue.extend(iter);
+            queue.into_sorted_vec()
+        });
+        let results = self.get_context().run_job(self.get_rdd(), take_partition)?;
+        let mut queue = BoundedPriorityQueue::new(num, cmp);
+        for result in results {
+            queue.extend(result);
+        }
+        Ok(queue.into_sorted_vec())
+    }
+
+    /// Return the top num elements of this RDD in descending order.
+    fn top(&self, num: usize) -> Result<Vec<T>>
+    where
+        T: Ord,
+        Self: Sized + 'static,
+    {
+        self.take_ordered(num, Fn!(|a: &T, b: &T| b.cmp(a)))
     }
 }
 
@@ -561,3 +757,282 @@ where
         //        Box::new(res.into_iter()) as Box<dyn Iterator<Item = U>>
     }
 }
+
+#[derive(Serialize, Deserialize)]
+pub struct FilterRdd<RT: 'static, T: Data, F>
+where
+    F: Fn(&T) -> bool + 'static + Send + Sync + Clone + Serialize + Deserialize,
+    RT: Rdd<T>,
+{
+    #[serde(with = "serde_traitobject")]
+    prev: Arc<RT>,
+    vals: Arc<RddVals>,
+    predicate: F,
+    _marker_t: PhantomData<T>,
+}
+
+impl<RT: 'static, T: Data, F> Clone for FilterRdd<RT, T, F>
+where
+    F: Fn(&T) -> bool + 'static + Send + Sync + Clone + Serialize + Deserialize,
+    RT: Rdd<T>,
+{
+    fn clone(&self) -> Self {
+        FilterRdd {
+            prev: self.prev.clone(),
+            vals: self.vals.clone(),
+            predicate: self.predicate.clone(),
+            _marker_t: PhantomData,
+        }
+    }
+}
+
+impl<RT: 'static, T: Data, F> FilterRdd<RT, T, F>
+where
+    F: SerFunc(&T) -> bool,
+    RT: Rdd<T>,
+{
+    fn new(prev: Arc<RT>, predicate: F) -> Self {
+        let mut vals = RddVals::new(prev.get_context());
+        vals.dependencies
+            .push(Dependency::OneToOneDependency(Arc::new(
+                OneToOneDependencyVals::new(prev.get_rdd_base()),
+            )));
+        let vals = Arc::new(vals);
+        FilterRdd {
+            prev,
+            vals,
+            predicate,
+            _marker_t: PhantomData,
+        }
+    }
+}
+
+impl<RT: 'static, T: Data, F> RddBase for FilterRdd<RT, T, F>
+where
+    F: SerFunc(&T) -> bool,
+    RT: Rdd<T>,
+{
+    fn get_rdd_id(&self) -> usize {
+        self.vals.id
+    }
+
+    fn get_context(&self) -> Context {
+        self.vals.context.clone()
+    }
+
+    fn get_dependencies(&self) -> &[Dependency] {
+        &self.vals.dependencies
+    }
+
+    fn splits(&self) -> Vec<Box<dyn Split>> {
+        self.prev.splits()
+    }
+    fn number_of_splits(&self) -> usize {
+        self.prev.number_of_splits()
+    }
+    // Filtering never moves elements between partitions, so the parent's partitioner still holds.
+    fn partitioner(&self) -> Option<Box<dyn Partitioner>> {
+        self.prev.partitioner()
+    }
+
+    default fn cogroup_iterator_any(
+        &self,
+        split: Box<dyn Split>,
+    ) -> Box<dyn Iterator<Item = Box<dyn AnyData>>> {
+        self.iterator_any(split)
+    }
+
+    default fn iterator_any(
+        &self,
+        split: Box<dyn Split>,
+    ) -> Box<dyn Iterator<Item = Box<dyn AnyData>>> {
+        info!("inside iterator_any filterrdd",);
+        Box::new(
+            self.iterator(split)
+                .map(|x| Box::new(x) as Box<dyn AnyData>),
+        )
+    }
+}
+
+impl<RT: 'static, K: Data, V: Data, F: 'static> RddBase for FilterRdd<RT, (K, V), F>
+where
+    F: SerFunc(&(K, V)) -> bool,
+    RT: Rdd<(K, V)>,
+{
+    fn cogroup_iterator_any(
+        &self,
+        split: Box<dyn Split>,
+    ) -> Box<dyn Iterator<Item = Box<dyn AnyData>>> {
+        info!("inside cogroup iterator_any filterrdd",);
+        Box::new(
+            self.iterator(split)
+                .map(|(k, v)| Box::new((k, Box::new(v) as Box<dyn AnyData>)) as Box<dyn AnyData>),
+        )
+    }
+}
+
+impl<RT: 'static, T: Data, F: 'static> Rdd<T> for FilterRdd<RT, T, F>
+where
+    F: SerFunc(&T) -> bool,
+    RT: Rdd<T>,
+{
+    fn get_rdd_base(&self) -> Arc<dyn RddBase> {
+        Arc::new(self.clone()) as Arc<dyn RddBase>
+    }
+    fn get_rdd(&self) -> Arc<Self> {
+        Arc::new(self.clone())
+    }
+    fn compute(&self, split: Box<dyn Split>) -> Box<dyn Iterator<Item = T>> {
+        let predicate = self.predicate.clone();
+        Box::new(self.prev.iterator(split).filter(move |x| predicate(x)))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MapPartitionsRdd<RT: 'static, T: Data, U: Data, F>
+where
+    F: Fn(Box<dyn Iterator<Item = T>>) -> Box<dyn Iterator<Item = U>>
+        + 'static
+        + Send
+        + Sync
+        + Clone
+        + Serialize
+        + Deserialize,
+    RT: Rdd<T>,
+{
+    #[serde(with = "serde_traitobject")]
+    prev: Arc<RT>,
+    vals: Arc<RddVals>,
+    f: F,
+    preserves_partitioning: bool,
+    _marker_t: PhantomData<T>,
+}
+
+impl<RT: 'static, T: Data, U: Data, F> Clone for MapPartitionsRdd<RT, T, U, F>
+where
+    F: Fn(Box<dyn Iterator<Item = T>>) -> Box<dyn Iterator<Item = U>>
+        + 'static
+        + Send
+        + Sync
+        + Clone
+        + Serialize
+        + Deserialize,
+    RT: Rdd<T>,
+{
+    fn clone(&self) -> Self {
+        MapPartitionsRdd {
+            prev: self.prev.clone(),
+            vals: self.vals.clone(),
+            f: self.f.clone(),
+            preserves_partitioning: self.preserves_partitioning,
+            _marker_t: PhantomData,
+        }
+    }
+}
+
+impl<RT: 'static, T: Data, U: Data, F> MapPartitionsRdd<RT, T, U, F>
+where
+    F: SerFunc(Box<dyn Iterator<Item = T>>) -> Box<dyn Iterator<Item = U>>,
+    RT: Rdd<T>,
+{
+    fn new(prev: Arc<RT>, f: F, preserves_partitioning: bool) -> Self {
+        let mut vals = RddVals::new(prev.get_context());
+        vals.dependencies
+            .push(Dependency::OneToOneDependency(Arc::new(
+                OneToOneDependencyVals::new(prev.get_rdd_base()),
+            )));
+        let vals = Arc::new(vals);
+        MapPartitionsRdd {
+            prev,
+            vals,
+            f,
+            preserves_partitioning,
+            _marker_t: PhantomData,
+        }
+    }
+}
+
+impl<RT: 'static, T: Data, U: Data, F> RddBase for MapPartitionsRdd<RT, T, U, F>
+where
+    F: SerFunc(Box<dyn Iterator<Item = T>>) -> Box<dyn Iterator<Item = U>>,
+    RT: Rdd<T>,
+{
+    fn get_rdd_id(&self) -> usize {
+        self.vals.id
+    }
+
+    fn get_context(&self) -> Context {
+        self.vals.context.clone()
+    }
+
+    fn get_dependencies(&self) -> &[Dependency] {
+        &self.vals.dependencies
+    }
+
+    fn splits(&self) -> Vec<Box<dyn Split>> {
+        self.prev.splits()
+    }
+    fn number_of_splits(&self) -> usize {
+        self.prev.number_of_splits()
+    }
+    // The function can change the keys, so the parent's partitioner is kept only when the caller says so.
+    fn partitioner(&self) -> Option<Box<dyn Partitioner>> {
+        if self.preserves_partitioning {
+            self.prev.partitioner()
+        } else {
+            None
+        }
+    }
+
+    default fn cogroup_iterator_any(
+        &self,
+        split: Box<dyn Split>,
+    ) -> Box<dyn Iterator<Item = Box<dyn AnyData>>> {
+        self.iterator_any(split)
+    }
+
+    default fn iterator_any(
+        &self,
+        split: Box<dyn Split>,
+    ) -> Box<dyn Iterator<Item = Box<dyn AnyData>>> {
+        info!("inside iterator_any mappartitionsrdd",);
+        Box::new(
+            self.iterator(split)
+                .map(|x| Box::new(x) as Box<dyn AnyData>),
+        )
+    }
+}
+
+impl<RT: 'static, T: Data, K: Data, V: Data, F: 'static> RddBase
+    for MapPartitionsRdd<RT, T, (K, V), F>
+where
+    F: SerFunc(Box<dyn Iterator<Item = T>>) -> Box<dyn Iterator<Item = (K, V)>>,
+    RT: Rdd<T>,
+{
+    fn cogroup_iterator_any(
+        &self,
+        split: Box<dyn Split>,
+    ) -> Box<dyn Iterator<Item = Box<dyn AnyData>>> {
+        info!("inside cogroup iterator_any mappartitionsrdd",);
+        Box::new(
+            self.iterator(split)
+                .map(|(k, v)| Box::new((k, Box::new(v) as Box<dyn AnyData>)) as Box<dyn AnyData>),
+        )
+    }
+}
+
+impl<RT: 'static, T: Data, U: Data, F: 'static> Rdd<U> for MapPartitionsRdd<RT, T, U, F>
+where
+    F: SerFunc(Box<dyn Iterator<Item = T>>) -> Box<dyn Iterator<Item = U>>,
+    RT: Rdd<T>,
+{
+    fn get_rdd_base(&self) -> Arc<dyn RddBase> {
+        Arc::new(self.clone()) as Arc<dyn RddBase>
+    }
+    fn get_rdd(&self) -> Arc<Self> {
+        Arc::new(self.clone())
+    }
+    fn compute(&self, split: Box<dyn Split>) -> Box<dyn Iterator<Item = U>> {
+        (self.f)(self.prev.iterator(split))
+    }
+}
@@ -1,6 +1,5 @@
 use super::*;
 use std::marker::PhantomData;
-use std::net::Ipv4Addr;
 use std::sync::Arc;
 
 #[derive(Serialize, Deserialize)]
@@ -22,8 +21,13 @@ where
     pub rdd: Arc<RT>,
     pub func: Arc<F>,
     pub partition: usize,
-    pub locs: Vec<Ipv4Addr>,
+    // split of the rdd computed by the task, built by the scheduler since it may differ from
+    // rdd.splits()[partition] when the stage's partitions were coalesced
+    #[serde(with = "serde_traitobject")]
+    pub split: Box<dyn Split>,
+    pub locs: Vec<TaskLocation>,
     pub output_id: usize,
+    pub attempt_id: usize,
     _marker: PhantomData<T>,
 }
 
@@ -46,8 +50,10 @@ where
             rdd: self.rdd.clone(),
             func: self.func.clone(),
             partition: self.partition,
+            split: self.split.clone(),
             locs: self.locs.clone(),
             output_id: self.output_id,
+            attempt_id: self.attempt_id,
             _marker: PhantomData,
         }
     }
@@ -71,7 +77,8 @@ where
         rdd: Arc<RT>,
         func: Arc<F>,
         partition: usize,
-        locs: Vec<Ipv4Addr>,
+        split: Box<dyn Split>,
+        locs: Vec<TaskLocation>,
         output_id: usize,
     ) -> Self {
         ResultTask {
@@ -81,8 +88,10 @@ where
             rdd,
             func,
             partition,
+            split,
             locs,
             output_id,
+            attempt_id: 0,
             _marker: PhantomData,
         }
     }
@@ -114,7 +123,11 @@ where
     fn get_task_id(&self) -> usize {
         self.task_id
     }
-    fn preferred_locations(&self) -> Vec<Ipv4Addr> {
+
+    fn get_attempt_id(&self) -> usize {
+        self.attempt_id
+    }
+    fn preferred_locations(&self) -> Vec<TaskLocation> {
         self.locs.clone()
     }
     fn generation(&self) -> Option<i64> {
@@ -136,8 +149,8 @@ where
         + Clone,
 {
     fn run(&self, id: usize) -> serde_traitobject::Box<dyn serde_traitobject::Any + Send + Sync> {
-        let split = self.rdd.splits()[self.partition].clone();
-        let context = TasKContext::new(self.stage_id, self.partition, id);
+        let split = self.split.clone();
+        let context = TasKContext::new(self.stage_id, self.partition, id, self.run_id);
         serde_traitobject::Box::new((self.func)((context, self.rdd.iterator(split))))
             as serde_traitobject::Box<dyn serde_traitobject::Any + Send + Sync>
     }
@@ -1,19 +0,0 @@
-use super::*;
-
-pub trait Scheduler {
-    fn start(&self);
-    fn wait_for_register(&self);
-    fn run_job<T: Data, U: Data, RT, F>(
-        &self,
-        rdd: &RT,
-        func: F,
-        partitions: Vec<i64>,
-        allow_local: bool,
-    ) -> Vec<U>
-    where
-        Self: Sized,
-        RT: Rdd<T>,
-        F: Fn(Box<dyn Iterator<Item = T>>) -> U;
-    fn stop(&self);
-    fn default_parallelism(&self) -> i64;
-}
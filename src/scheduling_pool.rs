@@ -0,0 +1,375 @@
+use super::*;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+pub(crate) const default_pool: &str = "default";
+
+thread_local! {
+    // pool of the jobs started from this thread, set with Context::set_job_pool
+    static job_pool: RefCell<Option<String>> = RefCell::new(None);
+}
+
+pub(crate) fn set_current_pool(pool: Option<String>) {
+    job_pool.with(|p| *p.borrow_mut() = pool);
+}
+
+pub(crate) fn current_pool() -> Option<String> {
+    job_pool.with(|p| p.borrow().clone())
+}
+
+// How the task slots are shared between concurrent jobs, chosen with SPARK_SCHEDULER_MODE.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SchedulingMode {
+    // tasks of earlier jobs run first
+    Fifo,
+    // slots are shared between the pools by their min share and weight, jobs within a pool run in FIFO order
+    Fair,
+}
+
+impl FromStr for SchedulingMode {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_lowercase()[..] {
+            "fifo" => Ok(SchedulingMode::Fifo),
+            "fair" => Ok(SchedulingMode::Fair),
+            _ => Err(format!("unknown scheduling mode {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PoolConfig {
+    // number of slots the pool gets before the other pools are considered by weight
+    pub min_share: usize,
+    pub weight: usize,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            min_share: 0,
+            weight: 1,
+        }
+    }
+}
+
+// Pool settings from SPARK_SCHEDULER_POOLS, a comma separated list of name:min_share:weight.
+// Pools which are not listed use the default config.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PoolConfigs(pub HashMap<String, PoolConfig>);
+
+impl FromStr for PoolConfigs {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut configs = HashMap::new();
+        for pool in s.split(',').map(str::trim).filter(|pool| !pool.is_empty()) {
+            let parts = pool.split(':').collect::<Vec<_>>();
+            let parse = |part: &str| {
+                part.parse::<usize>()
+                    .map_err(|_| format!("invalid pool config {}", pool))
+            };
+            match parts[..] {
+                [name, min_share, weight] => {
+                    let config = PoolConfig {
+                        min_share: parse(min_share)?,
+                        weight: parse(weight)?,
+                    };
+                    configs.insert(name.to_string(), config);
+                }
+                _ => return Err(format!("invalid pool config {}", pool)),
+            }
+        }
+        Ok(PoolConfigs(configs))
+    }
+}
+
+// A task waiting for a slot. run starts it on the executor with the given index and returns once
+// the task ended.
+pub(crate) struct QueuedTask {
+    pub task_id: usize,
+    locations: Vec<TaskLocation>,
+    queued_at: Instant,
+    pub run: Box<dyn FnOnce(usize) + Send>,
+}
+
+#[derive(Default)]
+struct Pool {
+    running: usize,
+    // queued tasks ordered by run id, stage id and task id, so earlier jobs come first
+    tasks: BTreeMap<(usize, usize, usize), QueuedTask>,
+}
+
+// Tasks waiting for a slot, grouped by the pool of their job, along with the slots of the backend's
+// executors. Every free slot takes the next task chosen by the scheduling mode which accepts it.
+pub(crate) struct SchedulingPools {
+    mode: SchedulingMode,
+    configs: HashMap<String, PoolConfig>,
+    pools: HashMap<String, Pool>,
+    slots: ExecutorSlots,
+    waits: LocalityWaits,
+}
+
+impl SchedulingPools {
+    pub fn new(
+        mode: SchedulingMode,
+        configs: PoolConfigs,
+        waits: LocalityWaits,
+        executors: Vec<ExecutorResources>,
+    ) -> Self {
+        SchedulingPools {
+            mode,
+            configs: configs.0,
+            pools: HashMap::new(),
+            slots: ExecutorSlots::new(executors),
+            waits,
+        }
+    }
+
+    pub fn total_slots(&self) -> usize {
+        self.slots.total()
+    }
+
+    pub fn push(
+        &mut self,
+        pool: &str,
+        key: (usize, usize, usize),
+        locations: Vec<TaskLocation>,
+        run: Box<dyn FnOnce(usize) + Send>,
+    ) {
+        let task = QueuedTask {
+            task_id: key.2,
+            locations,
+            queued_at: Instant::now(),
+            run,
+        };
+        self.pools
+            .entry(pool.to_string())
+            .or_default()
+            .tasks
+            .insert(key, task);
+    }
+
+    // Takes the next task which gets a free slot, along with the name of its pool and the index of
+    // the executor, which have to be given back to task_done. Delay scheduling: a task only takes a
+    // slot worse than the best level offered to it once it waited long enough for a better one, in
+    // the meantime the slot goes to the next task. None if no queued task accepts a free slot.
+    pub fn pop(&mut self, now: Instant) -> Option<(String, usize, QueuedTask)> {
+        for name in self.pool_order() {
+            let pool = self.pools.get_mut(&name).unwrap();
+            let mut launched = None;
+            for (key, task) in &pool.tasks {
+                let waited = if now > task.queued_at {
+                    now - task.queued_at
+                } else {
+                    Duration::from_secs(0)
+                };
+                let best = self.slots.best_locality(task.task_id, &task.locations);
+                let level = self.waits.allowed_level(best, waited);
+                if let Some(executor) = self.slots.acquire(task.task_id, &task.locations, level) {
+                    launched = Some((*key, executor));
+                    break;
+                }
+            }
+            if let Some((key, executor)) = launched {
+                let task = pool.tasks.remove(&key).unwrap();
+                pool.running += 1;
+                return Some((name, executor, task));
+            }
+        }
+        None
+    }
+
+    pub fn task_done(&mut self, pool: &str, executor: usize, task_id: usize) {
+        if let Some(pool) = self.pools.get_mut(pool) {
+            pool.running -= 1;
+        }
+        self.slots.release(executor, task_id);
+    }
+
+    // Pools with queued tasks in the order they are offered a free slot.
+    fn pool_order(&self) -> Vec<String> {
+        let mut waiting = self
+            .pools
+            .iter()
+            .filter(|(_, pool)| !pool.tasks.is_empty())
+            .collect::<Vec<_>>();
+        match self.mode {
+            SchedulingMode::Fifo => {
+                waiting.sort_by_key(|(_, pool)| *pool.tasks.keys().next().unwrap())
+            }
+            SchedulingMode::Fair => {
+                waiting.sort_by(|(n1, p1), (n2, p2)| self.compare_fair(n1, p1, n2, p2))
+            }
+        }
+        waiting.into_iter().map(|(name, _)| name.clone()).collect()
+    }
+
+    // Pools below their min share come first, the neediest first. The others are ordered by running
+    // tasks per weight.
+    fn compare_fair(&self, n1: &str, p1: &Pool, n2: &str, p2: &Pool) -> Ordering {
+        let c1 = self.configs.get(n1).cloned().unwrap_or_default();
+        let c2 = self.configs.get(n2).cloned().unwrap_or_default();
+        let needy1 = p1.running < c1.min_share;
+        let needy2 = p2.running < c2.min_share;
+        let ratio = |running: usize, share: usize| running as f64 / share.max(1) as f64;
+        let order = match (needy1, needy2) {
+            (true, false) => Ordering::Less,
+            (false, true) => Ordering::Greater,
+            (true, true) => ratio(p1.running, c1.min_share)
+                .partial_cmp(&ratio(p2.running, c2.min_share))
+                .unwrap(),
+            (false, false) => ratio(p1.running, c1.weight)
+                .partial_cmp(&ratio(p2.running, c2.weight))
+                .unwrap(),
+        };
+        order.then_with(|| n1.cmp(n2))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    const no_waits: LocalityWaits = LocalityWaits {
+        process: Duration::from_secs(0),
+        node: Duration::from_secs(0),
+    };
+
+    fn executor(address: &str, cores: usize) -> ExecutorResources {
+        ExecutorResources {
+            host: address.parse().ok(),
+            cores,
+        }
+    }
+
+    fn new_pools(mode: SchedulingMode, configs: PoolConfigs) -> SchedulingPools {
+        SchedulingPools::new(mode, configs, no_waits, vec![executor("local", 16)])
+    }
+
+    fn push_tasks(pools: &mut SchedulingPools, pool: &str, run_id: usize, count: usize) {
+        for task_id in 0..count {
+            pools.push(pool, (run_id, 0, task_id), Vec::new(), Box::new(|_| ()));
+        }
+    }
+
+    fn next_pools(pools: &mut SchedulingPools, count: usize) -> Vec<String> {
+        (0..count)
+            .map(|_| pools.pop(Instant::now()).unwrap().0)
+            .collect()
+    }
+
+    #[test]
+    fn fifo_runs_earlier_jobs_first() {
+        let mut pools = new_pools(SchedulingMode::Fifo, PoolConfigs::default());
+        push_tasks(&mut pools, "b", 2, 2);
+        push_tasks(&mut pools, "a", 1, 2);
+        assert_eq!(next_pools(&mut pools, 4), vec!["a", "a", "b", "b"]);
+        assert!(pools.pop(Instant::now()).is_none());
+    }
+
+    #[test]
+    fn fair_shares_slots_by_weight() {
+        let configs: PoolConfigs = "heavy:0:2,light:0:1".parse().unwrap();
+        let mut pools = new_pools(SchedulingMode::Fair, configs);
+        push_tasks(&mut pools, "heavy", 1, 10);
+        push_tasks(&mut pools, "light", 2, 10);
+        let picked = next_pools(&mut pools, 6);
+        assert_eq!(picked.iter().filter(|p| *p == "heavy").count(), 4);
+        assert_eq!(picked.iter().filter(|p| *p == "light").count(), 2);
+    }
+
+    #[test]
+    fn fair_serves_min_share_first() {
+        let configs: PoolConfigs = "etl:2:1".parse().unwrap();
+        let mut pools = new_pools(SchedulingMode::Fair, configs);
+        push_tasks(&mut pools, "adhoc", 1, 5);
+        let (pool, executor, task) = pools.pop(Instant::now()).unwrap();
+        assert_eq!(pool, "adhoc");
+        push_tasks(&mut pools, "etl", 2, 5);
+        assert_eq!(next_pools(&mut pools, 2), vec!["etl", "etl"]);
+        pools.task_done("adhoc", executor, task.task_id);
+        assert_eq!(next_pools(&mut pools, 1), vec!["adhoc"]);
+    }
+
+    #[test]
+    fn queued_tasks_are_run() {
+        let ran = Arc::new(Mutex::new(Vec::new()));
+        let mut pools = SchedulingPools::new(
+            SchedulingMode::Fifo,
+            PoolConfigs::default(),
+            no_waits,
+            vec![executor("local", 1)],
+        );
+        for task_id in 0..3 {
+            let ran = ran.clone();
+            let run =
+                Box::new(move |executor: usize| ran.lock().unwrap().push((task_id, executor)));
+            pools.push(default_pool, (0, 0, task_id), Vec::new(), run);
+        }
+        while let Some((pool, executor, task)) = pools.pop(Instant::now()) {
+            // the only slot is taken until the task is done
+            assert!(pools.pop(Instant::now()).is_none());
+            (task.run)(executor);
+            pools.task_done(&pool, executor, task.task_id);
+        }
+        assert_eq!(*ran.lock().unwrap(), vec![(0, 0), (1, 0), (2, 0)]);
+    }
+
+    #[test]
+    fn busy_local_slot_goes_to_next_task() {
+        let waits = LocalityWaits {
+            process: Duration::from_secs(3),
+            node: Duration::from_secs(3),
+        };
+        let executors = vec![executor("10.0.0.1", 1), executor("10.0.0.2", 1)];
+        let mut pools = SchedulingPools::new(
+            SchedulingMode::Fifo,
+            PoolConfigs::default(),
+            waits,
+            executors,
+        );
+        let cached = vec![TaskLocation::Executor("10.0.0.1".parse().unwrap())];
+        pools.push(default_pool, (0, 0, 0), cached.clone(), Box::new(|_| ()));
+        pools.push(default_pool, (0, 0, 1), cached.clone(), Box::new(|_| ()));
+        pools.push(default_pool, (0, 0, 2), Vec::new(), Box::new(|_| ()));
+        let now = Instant::now();
+        let (_, executor, task) = pools.pop(now).unwrap();
+        assert_eq!((executor, task.task_id), (0, 0));
+        // task 1 waits for the busy executor caching its partition, the free slot runs task 2
+        let (_, executor, task) = pools.pop(now).unwrap();
+        assert_eq!((executor, task.task_id), (1, 2));
+        assert!(pools.pop(now).is_none());
+        pools.task_done(default_pool, 0, 0);
+        let (_, executor, task) = pools.pop(now).unwrap();
+        assert_eq!((executor, task.task_id), (0, 1));
+    }
+
+    #[test]
+    fn waiting_task_falls_back_to_any_executor() {
+        let waits = LocalityWaits {
+            process: Duration::from_secs(3),
+            node: Duration::from_secs(3),
+        };
+        let executors = vec![executor("10.0.0.1", 1), executor("10.0.0.2", 1)];
+        let mut pools = SchedulingPools::new(
+            SchedulingMode::Fifo,
+            PoolConfigs::default(),
+            waits,
+            executors,
+        );
+        let cached = vec![TaskLocation::Executor("10.0.0.1".parse().unwrap())];
+        pools.push(default_pool, (0, 0, 0), cached.clone(), Box::new(|_| ()));
+        pools.push(default_pool, (0, 0, 1), cached, Box::new(|_| ()));
+        let queued = Instant::now();
+        assert_eq!(pools.pop(queued).unwrap().1, 0);
+        assert!(pools.pop(queued).is_none());
+        // after the process wait only a node local executor would do, there is none
+        assert!(pools.pop(queued + waits.process).is_none());
+        let (_, executor, task) = pools.pop(queued + waits.process + waits.node).unwrap();
+        assert_eq!((executor, task.task_id), (1, 1));
+    }
+}
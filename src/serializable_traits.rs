@@ -131,6 +131,11 @@ impl Box<dyn AnyData> {
 }
 
 impl<T: ?Sized + marker::Unsize<U>, U: ?Sized> ops::CoerceUnsized<Box<U>> for Box<T> {}
+impl<T: objekt::Clone + ?Sized> Clone for Box<T> {
+    fn clone(&self) -> Self {
+        Self(objekt::clone_box(&*self.0))
+    }
+}
 impl<T: ?Sized> Deref for Box<T> {
     type Target = boxed::Box<T>;
     fn deref(&self) -> &Self::Target {
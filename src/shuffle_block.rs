@@ -0,0 +1,191 @@
+use super::*;
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+
+// Codec used for compressing shuffle blocks, chosen with the SPARK_SHUFFLE_COMPRESSION environment variable.
+// Each block records the codec it was written with, so readers don't depend on the configuration.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CompressionCodec {
+    None,
+    Lz4,
+    Zstd,
+}
+
+impl CompressionCodec {
+    fn id(self) -> u8 {
+        match self {
+            CompressionCodec::None => 0,
+            CompressionCodec::Lz4 => 1,
+            CompressionCodec::Zstd => 2,
+        }
+    }
+
+    fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(CompressionCodec::None),
+            1 => Some(CompressionCodec::Lz4),
+            2 => Some(CompressionCodec::Zstd),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for CompressionCodec {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_lowercase()[..] {
+            "none" => Ok(CompressionCodec::None),
+            "lz4" => Ok(CompressionCodec::Lz4),
+            "zstd" => Ok(CompressionCodec::Zstd),
+            _ => Err(format!("unknown compression codec {}", s)),
+        }
+    }
+}
+
+// Block layout: [codec id: u8][payload compressed with the codec][crc32 of the codec id and payload: u32 LE].
+// The checksum comes last so that blocks can be written in a streaming fashion.
+// Writes one block, with the uncompressed payload produced by `write_payload`, and returns the number of bytes written.
+pub(crate) fn write_block<F>(
+    codec: CompressionCodec,
+    out: &mut dyn Write,
+    write_payload: F,
+) -> io::Result<u64>
+where
+    F: FnOnce(&mut dyn Write) -> io::Result<()>,
+{
+    let mut writer = ChecksumWriter {
+        inner: out,
+        hasher: crc32fast::Hasher::new(),
+        written: 0,
+    };
+    writer.write_all(&[codec.id()])?;
+    match codec {
+        CompressionCodec::None => write_payload(&mut writer)?,
+        CompressionCodec::Lz4 => {
+            let mut encoder = lz4::EncoderBuilder::new().build(&mut writer)?;
+            write_payload(&mut encoder)?;
+            encoder.finish().1?;
+        }
+        CompressionCodec::Zstd => {
+            let mut encoder = zstd::stream::Encoder::new(&mut writer, 0)?;
+            write_payload(&mut encoder)?;
+            encoder.finish()?;
+        }
+    }
+    let ChecksumWriter {
+        inner,
+        hasher,
+        written,
+    } = writer;
+    inner.write_all(&hasher.finalize().to_le_bytes())?;
+    Ok(written + 4)
+}
+
+pub(crate) fn encode_block(codec: CompressionCodec, payload: &[u8]) -> io::Result<Vec<u8>> {
+    let mut block = Vec::new();
+    write_block(codec, &mut block, |writer| writer.write_all(payload))?;
+    Ok(block)
+}
+
+/// Verifies the checksum of a block and returns its decompressed payload.
+/// Corrupted blocks result in an error of kind InvalidData.
+pub(crate) fn decode_block(block: &[u8]) -> io::Result<Vec<u8>> {
+    verify_block(block)?;
+    let mut bytes = Vec::new();
+    block_reader(block)?.read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+pub(crate) fn verify_block(block: &[u8]) -> io::Result<()> {
+    if block.len() < 5 {
+        return Err(invalid_block(format!(
+            "block of {} bytes is too short",
+            block.len()
+        )));
+    }
+    let (content, checksum) = block.split_at(block.len() - 4);
+    let mut expected = [0u8; 4];
+    expected.copy_from_slice(checksum);
+    let expected = u32::from_le_bytes(expected);
+    let actual = crc32fast::hash(content);
+    if actual != expected {
+        return Err(invalid_block(format!(
+            "checksum mismatch, expected {:x} but got {:x}",
+            expected, actual
+        )));
+    }
+    Ok(())
+}
+
+/// Returns a reader decompressing the payload of a block, without verifying the checksum.
+pub(crate) fn block_reader<'a>(block: &'a [u8]) -> io::Result<Box<dyn Read + 'a>> {
+    if block.len() < 5 {
+        return Err(invalid_block(format!(
+            "block of {} bytes is too short",
+            block.len()
+        )));
+    }
+    let payload = &block[1..block.len() - 4];
+    match CompressionCodec::from_id(block[0]) {
+        Some(CompressionCodec::None) => Ok(Box::new(payload)),
+        Some(CompressionCodec::Lz4) => Ok(Box::new(lz4::Decoder::new(payload)?)),
+        Some(CompressionCodec::Zstd) => Ok(Box::new(zstd::stream::Decoder::new(payload)?)),
+        None => Err(invalid_block(format!("unknown codec id {}", block[0]))),
+    }
+}
+
+fn invalid_block(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+struct ChecksumWriter<W: Write> {
+    inner: W,
+    hasher: crc32fast::Hasher,
+    written: u64,
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_round_trip() {
+        let payload = (0..10_000u32)
+            .flat_map(|i| (i % 7).to_le_bytes().to_vec())
+            .collect::<Vec<_>>();
+        let codecs = [
+            CompressionCodec::None,
+            CompressionCodec::Lz4,
+            CompressionCodec::Zstd,
+        ];
+        for codec in &codecs {
+            let block = encode_block(*codec, &payload).unwrap();
+            assert_eq!(decode_block(&block).unwrap(), payload);
+            let empty = encode_block(*codec, &[]).unwrap();
+            assert!(decode_block(&empty).unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn corrupted_block_is_rejected() {
+        let mut block = encode_block(CompressionCodec::Lz4, b"some shuffle data").unwrap();
+        let middle = block.len() / 2;
+        block[middle] ^= 1;
+        let err = decode_block(&block).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(decode_block(&block[..3]).is_err());
+    }
+}
@@ -1,118 +1,491 @@
 use super::*;
-use parking_lot::Mutex;
-use std::collections::HashMap;
+use parking_lot::{Condvar, Mutex};
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
 use std::io::Read;
-use std::sync::mpsc::channel;
-use std::sync::mpsc::Sender;
+use std::sync::mpsc::{channel, Sender};
 use std::sync::Arc;
 //use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
 use threadpool::ThreadPool;
 
+// Maximum number of map outputs requested from a server at once.
+const MAX_MAPS_PER_REQUEST: usize = 128;
+
 pub struct ShuffleFetcher;
 
 // Parallel shuffle fetcher. Instead of Thread, convert everything to Tokio based async methods
 impl ShuffleFetcher {
+    /// Fetches the bucket `reduce_id` of all map outputs of the shuffle and passes every record to `func`.
+    /// Fetching a block is retried with exponential backoff. When it still fails, the location of the missing
+    /// map output is returned. Tasks abort with it as panic payload, so that the scheduler recomputes the output.
     pub fn fetch<K: Data, V: Data>(
         &self,
         sc: Context,
         shuffle_id: usize,
         reduce_id: usize,
         mut func: impl FnMut((K, V)) -> (),
-    ) {
+    ) -> Result<(), FetchFailedVals> {
         info!("inside fetch function");
-        let parallel_fetches = 10; //TDOD  make this as env variable
-        let thread_pool = ThreadPool::new(parallel_fetches);
-        let mut inputs_by_uri = HashMap::new();
-        let server_uris = env::env
+        let map_statuses = env::env
             .map_output_tracker
             //            .lock()
             //            .expect("problem in getting lock for mapoutput tracker")
-            .get_server_uris(shuffle_id);
+            .get_map_statuses(shuffle_id);
         info!(
-            "server uris for shuffle id {:?} - {:?}",
-            shuffle_id, server_uris
+            "map statuses for shuffle id {:?} - {:?}",
+            shuffle_id, map_statuses
         );
-        for (index, server_uri) in server_uris.clone().into_iter().enumerate() {
-            inputs_by_uri
-                .entry(server_uri)
-                .or_insert(Vec::new())
-                .push(index);
+        // Outputs of map tasks which ran in this process are read directly, the rest is requested in batches per server.
+        let local_uri = env::env.shuffle_manager.get_server_uri();
+        let mut local_inputs = Vec::new();
+        let mut inputs_by_uri = HashMap::new();
+        for (index, status) in map_statuses.into_iter().enumerate() {
+            // empty buckets are known from the map status and not fetched at all
+            if status.get_record_count_for_block(reduce_id) == 0 {
+                continue;
+            }
+            let server_uri = status.server_uri;
+            if server_uri == local_uri {
+                local_inputs.push(index);
+            } else {
+                inputs_by_uri
+                    .entry(server_uri)
+                    .or_insert_with(Vec::new)
+                    .push(index);
+            }
         }
-        let server_queue = Arc::new(Mutex::new(Vec::new()));
         let total_results: usize = inputs_by_uri.iter().map(|(_, v)| v.len()).sum();
         //TODO see whether randomize is necessary or not
-        for (key, value) in inputs_by_uri {
-            server_queue.lock().push((key, value));
+        let mut requests = Vec::new();
+        for (server_uri, map_ids) in inputs_by_uri {
+            for chunk in map_ids.chunks(MAX_MAPS_PER_REQUEST) {
+                requests.push((server_uri.clone(), chunk.to_vec()));
+            }
         }
         info!(
-            "servers for shuffle id {:?}, reduce id {:?} - {:?}",
-            shuffle_id, reduce_id, server_queue
+            "requests for shuffle id {:?}, reduce id {:?} - {:?}",
+            shuffle_id, reduce_id, requests
         );
-        //        let log_output = format!("servers {:?}", server_queue);
+
+        let remote_inputs = requests
+            .iter()
+            .flat_map(|(server_uri, map_ids)| {
+                map_ids
+                    .iter()
+                    .map(move |&map_id| (server_uri.clone(), map_id))
+            })
+            .collect::<Vec<_>>();
+        let server_queue = Arc::new(Mutex::new(requests));
+        let budget = Arc::new(InFlightBudget::new(env::config.shuffle_max_bytes_in_flight));
+        // stops the fetching threads when returning early
+        let _abort_guard = AbortOnDrop(budget.clone());
         let (producer, consumer) = channel();
         let failure = Arc::new(Mutex::new(None));
-        let sent_count = Arc::new(Mutex::new(0));
-        for i in 0..parallel_fetches {
-            info!("inisde parallel fetch {}", i);
+        let parallel_fetches = env::config.shuffle_fetch_parallelism;
+        let thread_pool = ThreadPool::new(parallel_fetches);
+        for _ in 0..parallel_fetches {
             let server_queue = server_queue.clone();
             let producer = producer.clone();
             let failure = failure.clone();
-            let sent_count_clone = sent_count.clone();
+            let budget = budget.clone();
 
             thread_pool.execute(move || {
-                while let Some((server_uri, input_ids)) = server_queue.lock().pop() {
-                    for i in input_ids {
+                while let Some((server_uri, map_ids)) = server_queue.lock().pop() {
+                    let mut fetched = 0;
+                    let mut retries = 0;
+                    loop {
                         if !failure.lock().is_none() {
                             return;
                         }
-                        let url =
-                            format!("{}/shuffle/{}/{}/{}", server_uri, shuffle_id, i, reduce_id);
-                        //TODO logging
-                        fn f(
-                            producer: Sender<(Vec<u8>, String)>,
-                            url: String,
-                        ) -> Result<(), Box<dyn Error>> {
-                            let mut res = reqwest::get(&url)?;
-                            let len = &res.content_length();
-                            let mut body = vec![0; len.unwrap() as usize];
-                            res.read_exact(&mut body)?;
-                            producer.send((body, url))?;
-                            Ok(())
-                        }
-                        let producer_clone = producer.clone();
-                        f(producer_clone, url).map_err(|e|
-                                //TODO change e to FailedFetchException
-                                *failure.lock() = Some(e.to_string()));
-                        *sent_count_clone.lock() += 1;
-                        info!(
-                            "total results {} results sent {:?}",
-                            total_results, sent_count_clone
-                        );
-                        info!(
-                            "total results {} results sent {:?}",
-                            total_results, sent_count_clone
+                        let result = fetch_batch(
+                            &server_uri,
+                            shuffle_id,
+                            reduce_id,
+                            &map_ids,
+                            &mut fetched,
+                            &budget,
+                            &producer,
                         );
+                        match result {
+                            Ok(()) => break,
+                            Err(e) if retries < env::config.shuffle_fetch_retries => {
+                                let wait = env::config.shuffle_fetch_retry_wait << retries;
+                                info!(
+                                    "fetching from {} failed, retrying in {} ms: {}",
+                                    server_uri, wait, e
+                                );
+                                thread::sleep(Duration::from_millis(wait));
+                                retries += 1;
+                            }
+                            Err(e) => {
+                                error!(
+                                    "failed to fetch shuffle block of map {} from {}: {}",
+                                    map_ids[fetched], server_uri, e
+                                );
+                                *failure.lock() = Some(FetchFailedVals {
+                                    server_uri: server_uri.clone(),
+                                    shuffle_id,
+                                    map_id: map_ids[fetched],
+                                    reduce_id,
+                                });
+                                return;
+                            }
+                        }
                     }
                 }
             })
         }
         info!("total_results {}", total_results);
+        // only the fetching threads hold senders now, so receiving fails once all of them are done
+        drop(producer);
+
+        for map_id in local_inputs {
+            let result = env::env
+                .shuffle_manager
+                .get_map_output(shuffle_id, map_id, reduce_id)
+                .and_then(|block| verify_block(&block).map(|_| block))
+                .map_err(|e| Box::new(e) as Box<dyn Error>)
+                .and_then(|block| read_records(&block, &mut func));
+            if let Err(e) = result {
+                error!(
+                    "unable to read local shuffle block of map {}: {}",
+                    map_id, e
+                );
+                return Err(FetchFailedVals {
+                    server_uri: local_uri,
+                    shuffle_id,
+                    map_id,
+                    reduce_id,
+                });
+            }
+        }
 
-        let mut results_done = 0;
-        while failure.lock().is_none() && (results_done < total_results) {
-            let (result, url) = consumer.recv().unwrap();
+        let mut received = HashSet::new();
+        while failure.lock().is_none() && (received.len() < total_results) {
+            let (block, map_id, server_uri) = match consumer.recv() {
+                Ok(block) => block,
+                Err(_) => break,
+            };
             info!(
                 "total results {} results done {}",
-                total_results, results_done
+                total_results,
+                received.len()
             );
-            info!("received from consumer");
-            let input: Vec<(K, V)> =
-                bincode::deserialize(&result).expect("not able to serialize fetched data");
-            for (k, v) in input {
-                func((k, v));
+            let result = read_records(&block, &mut func);
+            budget.release(block.len());
+            if let Err(e) = result {
+                error!(
+                    "unable to read shuffle block of map {} from {}: {}",
+                    map_id, server_uri, e
+                );
+                return Err(FetchFailedVals {
+                    server_uri,
+                    shuffle_id,
+                    map_id,
+                    reduce_id,
+                });
+            }
+            received.insert(map_id);
+        }
+        if let Some(failed) = failure.lock().take() {
+            return Err(failed);
+        }
+        // all fetching threads are gone, so the blocks still missing are never sent, e.g. after a thread panicked
+        match remote_inputs
+            .into_iter()
+            .find(|(_, map_id)| !received.contains(map_id))
+        {
+            Some((server_uri, map_id)) => {
+                error!(
+                    "fetching shuffle block of map {} from {} ended without a result",
+                    map_id, server_uri
+                );
+                Err(FetchFailedVals {
+                    server_uri,
+                    shuffle_id,
+                    map_id,
+                    reduce_id,
+                })
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+// Requests the blocks of `map_ids[*fetched..]` from one server and sends them to the consumer as they arrive,
+// counting them in `fetched`, so that a retry continues with the first block which was not received.
+// A response may end early to bound the memory of the server, then the remaining blocks are requested again.
+// Blocks are checked here, so that corrupted data is retried like any other failed request.
+fn fetch_batch(
+    server_uri: &str,
+    shuffle_id: usize,
+    reduce_id: usize,
+    map_ids: &[usize],
+    fetched: &mut usize,
+    budget: &InFlightBudget,
+    producer: &Sender<(Vec<u8>, usize, String)>,
+) -> Result<(), Box<dyn Error>> {
+    while *fetched < map_ids.len() {
+        let ids = map_ids[*fetched..]
+            .iter()
+            .map(|map_id| map_id.to_string())
+            .collect::<Vec<_>>();
+        let url = format!(
+            "{}/shuffle_batch/{}/{}/{}",
+            server_uri,
+            shuffle_id,
+            reduce_id,
+            ids.join(",")
+        );
+        let mut res = reqwest::get(&url)?.error_for_status()?;
+        let requested = *fetched;
+        while *fetched < map_ids.len() {
+            let map_id = map_ids[*fetched];
+            let len = match read_block_len(&mut res)? {
+                Some(len) => len,
+                None => break,
+            };
+            if len == MISSING_BLOCK {
+                return Err(format!("map output {} is missing on {}", map_id, server_uri).into());
+            }
+            let len = len as usize;
+            if !budget.acquire(len) {
+                return Ok(());
+            }
+            let mut block = vec![0; len];
+            let result = res
+                .read_exact(&mut block)
+                .and_then(|_| verify_block(&block));
+            if let Err(e) = result {
+                budget.release(len);
+                return Err(e.into());
+            }
+            if producer
+                .send((block, map_id, server_uri.to_string()))
+                .is_err()
+            {
+                // the consumer is gone after the fetch was aborted
+                budget.release(len);
+                return Ok(());
+            }
+            *fetched += 1;
+        }
+        if *fetched == requested {
+            return Err(format!("empty response from {}", server_uri).into());
+        }
+    }
+    Ok(())
+}
+
+// Reads the length preceding a block, None if the response ended before it.
+fn read_block_len(res: &mut impl Read) -> std::io::Result<Option<u64>> {
+    let mut len = [0u8; 8];
+    let mut read = 0;
+    while read < len.len() {
+        match res.read(&mut len[read..]) {
+            Ok(0) if read == 0 => return Ok(None),
+            Ok(0) => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => read += n,
+            Err(ref e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(Some(u64::from_le_bytes(len)))
+}
+
+// Deserializes the records of a block one by one while decompressing it, so the decompressed bucket is never
+// held in memory. The compressed block itself is, bounded by the in flight budget of the fetch.
+// The bucket has the format of a bincode serialized Vec<(K, V)>, a u64 record count followed by the records.
+// The checksum of the block is verified by the caller.
+fn read_records<K: Data, V: Data>(
+    block: &[u8],
+    func: &mut impl FnMut((K, V)) -> (),
+) -> Result<(), Box<dyn Error>> {
+    let mut reader = block_reader(block)?;
+    let mut count = [0u8; 8];
+    reader.read_exact(&mut count)?;
+    for _ in 0..u64::from_le_bytes(count) {
+        let record: (K, V) = bincode::deserialize_from(&mut reader)?;
+        func(record);
+    }
+    Ok(())
+}
+
+// Limits the bytes of fetched blocks which are waiting to be deserialized.
+// A block larger than the limit is still let through when nothing else is in flight.
+struct InFlightBudget {
+    max_bytes: usize,
+    // bytes in flight and whether the fetch was aborted
+    state: Mutex<(usize, bool)>,
+    released: Condvar,
+}
+
+impl InFlightBudget {
+    fn new(max_bytes: usize) -> Self {
+        InFlightBudget {
+            max_bytes,
+            state: Mutex::new((0, false)),
+            released: Condvar::new(),
+        }
+    }
+
+    // Waits until `bytes` fit into the budget. Returns false if the fetch was aborted in the meantime.
+    fn acquire(&self, bytes: usize) -> bool {
+        let mut state = self.state.lock();
+        while !state.1 && state.0 > 0 && state.0 + bytes > self.max_bytes {
+            self.released.wait(&mut state);
+        }
+        if state.1 {
+            return false;
+        }
+        state.0 += bytes;
+        true
+    }
+
+    fn release(&self, bytes: usize) {
+        self.state.lock().0 -= bytes;
+        self.released.notify_all();
+    }
+
+    fn abort(&self) {
+        self.state.lock().1 = true;
+        self.released.notify_all();
+    }
+}
+
+struct AbortOnDrop(Arc<InFlightBudget>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc::Receiver;
+
+    // Answers the next requests with the given bodies, one each, and records the requested paths.
+    fn serve(bodies: Vec<Vec<u8>>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let uri = format!("http://{}", listener.local_addr().unwrap());
+        let paths = Arc::new(Mutex::new(Vec::new()));
+        let requested = paths.clone();
+        thread::spawn(move || {
+            for body in bodies {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut header = String::new();
+                while reader.read_line(&mut header).unwrap() > 2 {
+                    header.clear();
+                }
+                let path = request_line.split(' ').nth(1).unwrap().to_string();
+                requested.lock().push(path);
+                // without a content length the body ends where the connection is closed
+                stream
+                    .write_all(b"HTTP/1.1 200 OK\r\nConnection: close\r\n\r\n")
+                    .unwrap();
+                stream.write_all(&body).unwrap();
             }
-            results_done += 1;
+        });
+        (uri, paths)
+    }
+
+    fn blocks() -> Vec<Vec<u8>> {
+        let payloads: [&[u8]; 3] = [b"first", b"second", b"third"];
+        payloads
+            .iter()
+            .map(|payload| encode_block(CompressionCodec::None, payload).unwrap())
+            .collect()
+    }
+
+    // The blocks as sent in a batch response, each one preceded by its length.
+    fn framed(blocks: &[Vec<u8>]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for block in blocks {
+            bytes.extend_from_slice(&(block.len() as u64).to_le_bytes());
+            bytes.extend_from_slice(block);
         }
+        bytes
+    }
+
+    fn received(consumer: &Receiver<(Vec<u8>, usize, String)>) -> Vec<(usize, Vec<u8>)> {
+        consumer
+            .try_iter()
+            .map(|(block, map_id, _)| (map_id, block))
+            .collect()
+    }
+
+    #[test]
+    fn retry_resumes_after_received_blocks() {
+        let blocks = blocks();
+        let mut truncated = framed(&blocks[..2]);
+        truncated.truncate(truncated.len() - 3);
+        let (uri, paths) = serve(vec![truncated, framed(&blocks[1..])]);
+        let budget = InFlightBudget::new(1 << 20);
+        let (producer, consumer) = channel();
+        let mut fetched = 0;
+        let map_ids = [4, 5, 6];
+        assert!(fetch_batch(&uri, 1, 2, &map_ids, &mut fetched, &budget, &producer).is_err());
+        assert_eq!(fetched, 1);
+        fetch_batch(&uri, 1, 2, &map_ids, &mut fetched, &budget, &producer).unwrap();
+        assert_eq!(fetched, 3);
+
+        let expected_paths = vec!["/shuffle_batch/1/2/4,5,6", "/shuffle_batch/1/2/5,6"];
+        assert_eq!(*paths.lock(), expected_paths);
+        let expected = vec![
+            (4, blocks[0].clone()),
+            (5, blocks[1].clone()),
+            (6, blocks[2].clone()),
+        ];
+        assert_eq!(received(&consumer), expected);
+    }
+
+    #[test]
+    fn ended_batch_requests_the_remaining_blocks() {
+        let blocks = blocks();
+        let (uri, paths) = serve(vec![framed(&blocks[..1]), framed(&blocks[1..])]);
+        let budget = InFlightBudget::new(1 << 20);
+        let (producer, consumer) = channel();
+        let mut fetched = 0;
+        fetch_batch(&uri, 0, 0, &[0, 1, 2], &mut fetched, &budget, &producer).unwrap();
+        assert_eq!(fetched, 3);
+        assert_eq!(paths.lock().len(), 2);
+        assert_eq!(received(&consumer).len(), 3);
+    }
+
+    #[test]
+    fn missing_block_fails_the_fetch() {
+        let blocks = blocks();
+        let mut body = framed(&blocks[..1]);
+        body.extend_from_slice(&MISSING_BLOCK.to_le_bytes());
+        let (uri, _) = serve(vec![body]);
+        let budget = InFlightBudget::new(1 << 20);
+        let (producer, consumer) = channel();
+        let mut fetched = 0;
+        let result = fetch_batch(&uri, 0, 0, &[0, 1], &mut fetched, &budget, &producer);
+        let error = result.unwrap_err().to_string();
+        assert!(error.contains("map output 1 is missing"), "{}", error);
+        assert_eq!(fetched, 1);
+        assert_eq!(received(&consumer).len(), 1);
+    }
+
+    #[test]
+    fn aborted_fetch_stops_without_error() {
+        let (uri, _) = serve(vec![framed(&blocks())]);
+        let budget = InFlightBudget::new(1 << 20);
+        budget.abort();
+        let (producer, consumer) = channel();
+        let mut fetched = 0;
+        fetch_batch(&uri, 0, 0, &[0, 1, 2], &mut fetched, &budget, &producer).unwrap();
+        assert_eq!(fetched, 0);
+        assert!(received(&consumer).is_empty());
     }
 }
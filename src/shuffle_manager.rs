@@ -3,19 +3,25 @@ use super::*;
 use actix_web::HttpServer;
 use actix_web::{
     get,
-    web::{Bytes, Path, Data},
-    App,
+    web::{Data, Path},
+    App, HttpResponse,
 };
 use rand::Rng;
 use std::collections::HashMap;
 use std::fs;
-use std::thread;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
 use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::Duration;
 use uuid::Uuid;
 
 type ShuffleCacheType = Arc<RwLock<HashMap<(usize, usize, usize), Vec<u8>>>>;
 
 // creates directories and files required for storing shuffle data.  It also creates the file server required for serving files via http request
+// Each map task writes its output either to the in memory shuffle cache when it is small,
+// or to {shuffle_dir}/{shuffle_id}/{map_id}.data holding all the buckets one after the other,
+// together with {map_id}.index holding the u64 start offsets of the buckets followed by the end offset of the last one.
 #[derive(Clone, Default, Debug, Serialize, Deserialize)]
 pub struct ShuffleManager {
     local_dir: String,
@@ -23,12 +29,13 @@ pub struct ShuffleManager {
     server_uri: String,
     server_address: String,
     shuffle_cache: ShuffleCacheType,
+    in_memory_threshold: usize,
 }
 
 impl ShuffleManager {
     pub fn new() -> Self {
-        //TODO replace all hardcoded values with environment variables
-        let local_dir_root = "/tmp";
+        let local_dir_root = env::config.local_dir.clone();
+        sweep_stale_dirs(&local_dir_root, "spark-local-");
         let mut tries = 0;
         let mut found_local_dir = false;
         let mut local_dir = String::new();
@@ -43,6 +50,10 @@ impl ShuffleManager {
             if !path.exists() {
                 info!("creating directory at path {:?} loc {:?}", path, local_dir);
                 fs::create_dir_all(path);
+                // lets the startup sweep of other processes know that this directory is still in use
+                if let Err(e) = write_pid_file(path) {
+                    error!("unable to write pid file in {:?}: {}", path, e);
+                }
                 found_local_dir = true;
             }
         }
@@ -66,9 +77,6 @@ impl ShuffleManager {
         info!("server_uri {:?}", server_uri);
         let server_address = format!("{}:{}", env::local_ip.clone(), port);
         info!("server_address {:?}", server_address);
-        let relative_path = format!("/spark-local-{}", local_dir_uuid);
-        let local_dir_clone = local_dir.clone();
-        info!("relative path {}", relative_path);
         info!("local_dir path {}", local_dir);
         info!("shuffle dir path {}", shuffle_dir);
         let shuffle_cache = Arc::new(RwLock::new(HashMap::new()));
@@ -79,35 +87,53 @@ impl ShuffleManager {
             server_uri,
             server_address,
             shuffle_cache,
+            in_memory_threshold: env::config.shuffle_in_memory_threshold,
         };
         info!("shuffle manager inside new {:?}", s);
+        s.start_server();
         s
     }
 
-    pub fn start_server(&self) {
-        let http_server_shuffle_cache = Data::new(&self.shuffle_cache);
+    fn start_server(&self) {
+        // The shuffle cache is behind an Arc, so the server shares it with the manager used by the map tasks.
+        let shuffle_manager = Data::new(self.clone());
         let server_address_clone = self.server_address.clone();
         thread::spawn(move || {
-
             #[get("/shuffle/{shuffleid}/{inputid}/{reduceid}")]
-            fn get_shuffle_data((info,cache): (Path<(usize, usize, usize)>, Data<ShuffleCacheType>)) -> Bytes {
-                //                println!("inside get shuffle data in  actix server");
-                //                println!(
-                //                    "bytes inside server {}",
-                //                    env::shuffle_cache
-                //                        .read()
-                //                        .unwrap()
-                //                        .get(&(info.0, info.1, info.2))
-                //                        .unwrap()
-                //                        .clone()[0]
-                //                );
-                Bytes::from(
-                    cache
-                        .read()
-                        .unwrap()
-                        .get(&(info.0, info.1, info.2))
-                        .unwrap()[..],
-                )
+            fn get_shuffle_data(
+                (info, shuffle_manager): (Path<(usize, usize, usize)>, Data<ShuffleManager>),
+            ) -> HttpResponse {
+                match shuffle_manager.get_map_output(info.0, info.1, info.2) {
+                    Ok(bytes) => HttpResponse::Ok().body(bytes),
+                    Err(e) => {
+                        error!(
+                            "unable to serve shuffle output {:?}: {}",
+                            (info.0, info.1, info.2),
+                            e
+                        );
+                        HttpResponse::NotFound().body(e.to_string())
+                    }
+                }
+            }
+            // Serves the blocks of several map outputs at once, see get_map_outputs for the format.
+            #[get("/shuffle_batch/{shuffleid}/{reduceid}/{mapids}")]
+            fn get_shuffle_batch(
+                (info, shuffle_manager): (Path<(usize, usize, String)>, Data<ShuffleManager>),
+            ) -> HttpResponse {
+                let map_ids = info
+                    .2
+                    .split(',')
+                    .map(|map_id| map_id.parse::<usize>())
+                    .collect::<Result<Vec<_>, _>>();
+                match map_ids {
+                    Ok(map_ids) => HttpResponse::Ok().body(shuffle_manager.get_map_outputs(
+                        info.0,
+                        info.1,
+                        &map_ids,
+                        MAX_BATCH_BYTES,
+                    )),
+                    Err(e) => HttpResponse::BadRequest().body(e.to_string()),
+                }
             }
             info!("starting server for shuffle task");
             #[get("/")]
@@ -116,13 +142,10 @@ impl ShuffleManager {
             }
             match HttpServer::new(move || {
                 App::new()
-                    .register_data(http_server_shuffle_cache)
+                    .register_data(shuffle_manager.clone())
                     .service(get_shuffle_data)
+                    .service(get_shuffle_batch)
                     .service(no_params)
-                //                    .service(
-                //                        // static files
-                //                        fserver::Files::new(&relative_path, &local_dir_clone),
-                //                    )
             })
             .workers(8)
             .bind(server_address_clone)
@@ -150,20 +173,369 @@ impl ShuffleManager {
         self.server_uri.clone()
     }
 
-    pub fn get_output_file(&self, shuffle_id: usize, input_id: usize, output_id: usize) -> String {
-        let path = format!("{}/{}/{}", self.shuffle_dir, shuffle_id, input_id);
-        fs::create_dir_all(&path);
-        let file_path = format!("{}/{}", path, output_id);
-        fs::File::create(file_path.clone());
-        file_path
+    fn shuffle_output_dir(&self, shuffle_id: usize) -> String {
+        format!("{}/{}", self.shuffle_dir, shuffle_id)
+    }
+
+    /// Stores the serialized buckets of one map task. Small outputs stay in memory, the rest is written
+    /// to a data file and an index file under the shuffle directory.
+    pub fn write_map_output(
+        &self,
+        shuffle_id: usize,
+        map_id: usize,
+        buckets: Vec<Vec<u8>>,
+    ) -> std::io::Result<()> {
+        let total_size: usize = buckets.iter().map(|b| b.len()).sum();
+        if total_size <= self.in_memory_threshold {
+            let mut cache = self.shuffle_cache.write().unwrap();
+            for (reduce_id, bucket) in buckets.into_iter().enumerate() {
+                cache.insert((shuffle_id, map_id, reduce_id), bucket);
+            }
+            Ok(())
+        } else {
+            let dir = self.shuffle_output_dir(shuffle_id);
+            fs::create_dir_all(&dir)?;
+            write_output_files(&dir, map_id, buckets.iter().map(|b| &b[..]))
+        }
+    }
+
+    /// Writes the output of a map task straight to the data and index files, bypassing the in memory cache.
+    /// Used when the output is too large to be assembled in memory, see write_output_files_with.
+    pub fn write_map_output_files<F>(
+        &self,
+        shuffle_id: usize,
+        map_id: usize,
+        num_buckets: usize,
+        write_bucket: F,
+    ) -> std::io::Result<()>
+    where
+        F: FnMut(usize, &mut dyn Write) -> std::io::Result<u64>,
+    {
+        let dir = self.shuffle_output_dir(shuffle_id);
+        fs::create_dir_all(&dir)?;
+        write_output_files_with(&dir, map_id, num_buckets, write_bucket)
+    }
+
+    /// Creates a new uniquely named file in the local directory for intermediate data like spills.
+    pub fn create_temp_file(&self) -> std::io::Result<(PathBuf, fs::File)> {
+        let dir = format!("{}/tmp", self.local_dir);
+        fs::create_dir_all(&dir)?;
+        let path = PathBuf::from(format!("{}/{}", dir, Uuid::new_v4()));
+        let file = fs::File::create(&path)?;
+        Ok((path, file))
+    }
+
+    /// Returns the serialized bucket `reduce_id` of the given map task, from memory or from the data file.
+    pub fn get_map_output(
+        &self,
+        shuffle_id: usize,
+        map_id: usize,
+        reduce_id: usize,
+    ) -> std::io::Result<Vec<u8>> {
+        if let Some(bytes) = self
+            .shuffle_cache
+            .read()
+            .unwrap()
+            .get(&(shuffle_id, map_id, reduce_id))
+        {
+            return Ok(bytes.clone());
+        }
+        read_output_range(&self.shuffle_output_dir(shuffle_id), map_id, reduce_id)
+    }
+
+    /// Returns the buckets `reduce_id` of several map tasks, each one preceded by its length as u64 LE.
+    /// Buckets which can't be read are logged and marked with a length of MISSING_BLOCK. Only the buckets
+    /// up to the one reaching `max_bytes` are returned, the fetcher requests the remaining ones again.
+    pub fn get_map_outputs(
+        &self,
+        shuffle_id: usize,
+        reduce_id: usize,
+        map_ids: &[usize],
+        max_bytes: usize,
+    ) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for &map_id in map_ids {
+            if bytes.len() >= max_bytes {
+                break;
+            }
+            match self.get_map_output(shuffle_id, map_id, reduce_id) {
+                Ok(block) => {
+                    bytes.extend_from_slice(&(block.len() as u64).to_le_bytes());
+                    bytes.extend_from_slice(&block);
+                }
+                Err(e) => {
+                    error!(
+                        "unable to serve shuffle output {:?}: {}",
+                        (shuffle_id, map_id, reduce_id),
+                        e
+                    );
+                    bytes.extend_from_slice(&MISSING_BLOCK.to_le_bytes());
+                }
+            }
+        }
+        bytes
     }
 
-    pub fn put_shuffle_cache(&self, shuffle_id: usize, partition: usize, idx: usize, ser_bytes: Vec<u8>) {
+    /// Frees the in memory outputs and deletes the output files of all map tasks of the shuffle.
+    pub fn remove_shuffle(&self, shuffle_id: usize) {
         self.shuffle_cache
             .write()
             .unwrap()
-            .insert((shuffle_id, partition, idx), ser_bytes);
+            .retain(|(id, _, _), _| *id != shuffle_id);
+        let dir = self.shuffle_output_dir(shuffle_id);
+        match fs::remove_dir_all(&dir) {
+            Ok(_) => info!("removed shuffle directory {}", dir),
+            Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => error!("unable to remove shuffle directory {}: {}", dir, e),
+        }
+    }
+
+    /// Frees the in memory output or deletes the output files of one map task, as when they are lost.
+    pub fn remove_map_output(&self, shuffle_id: usize, map_id: usize) {
+        self.shuffle_cache
+            .write()
+            .unwrap()
+            .retain(|(id, map, _), _| (*id, *map) != (shuffle_id, map_id));
+        let dir = self.shuffle_output_dir(shuffle_id);
+        for path in &[
+            format!("{}/{}.data", dir, map_id),
+            format!("{}/{}.index", dir, map_id),
+        ] {
+            match fs::remove_file(path) {
+                Ok(_) => {}
+                Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => error!("unable to remove map output file {}: {}", path, e),
+            }
+        }
+    }
+
+    /// Deletes the whole local directory. Called when the context or the executor shuts down.
+    pub fn clean_up(&self) {
+        self.shuffle_cache.write().unwrap().clear();
+        match fs::remove_dir_all(&self.local_dir) {
+            Ok(_) => info!("removed local directory {}", self.local_dir),
+            Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => error!("unable to remove local directory {}: {}", self.local_dir, e),
+        }
     }
 }
 
-//TODO implement drop for deleting files created when the shuffle manager stops
+// Length sent in place of a block which could not be read, see get_map_outputs.
+pub(crate) const MISSING_BLOCK: u64 = std::u64::MAX;
+
+// Size after which a batch response ends, so that serving a batch holds at most this much and one block in
+// memory, however many map outputs are requested at once.
+const MAX_BATCH_BYTES: usize = 4 * 1024 * 1024;
+
+const PID_FILE: &str = "pid";
+
+// Directories without a pid file are only considered stale after this long, since the pid file is written
+// right after the directory is created. Also applies to all directories on platforms where it can't be checked
+// whether the owner is running.
+const STALE_DIR_AGE: Duration = Duration::from_secs(60 * 60);
+
+pub(crate) fn write_pid_file(dir: &std::path::Path) -> std::io::Result<()> {
+    fs::write(dir.join(PID_FILE), std::process::id().to_string())
+}
+
+// Removes the directories in `root` starting with `prefix` whose owning process is not running anymore.
+// These are left behind by runs which crashed or were killed before cleaning up.
+pub(crate) fn sweep_stale_dirs(root: &str, prefix: &str) {
+    let entries = match fs::read_dir(root) {
+        Ok(entries) => entries,
+        Err(e) => {
+            error!(
+                "unable to read {} for sweeping stale directories: {}",
+                root, e
+            );
+            return;
+        }
+    };
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let path = entry.path();
+        let is_candidate = path.is_dir()
+            && entry
+                .file_name()
+                .to_str()
+                .map_or(false, |name| name.starts_with(prefix));
+        if is_candidate && is_stale_dir(&path) {
+            match fs::remove_dir_all(&path) {
+                Ok(_) => info!("removed stale directory {:?}", path),
+                Err(e) => error!("unable to remove stale directory {:?}: {}", path, e),
+            }
+        }
+    }
+}
+
+fn is_stale_dir(path: &std::path::Path) -> bool {
+    match fs::read_to_string(path.join(PID_FILE)) {
+        Ok(pid) => match pid.trim().parse::<u32>() {
+            Ok(pid) if pid == std::process::id() => false,
+            // Where there is no way to tell whether the owner is running, only old directories are removed.
+            Ok(pid) => is_process_alive(pid).map_or_else(|| is_old_dir(path), |alive| !alive),
+            Err(_) => false,
+        },
+        Err(_) => is_old_dir(path),
+    }
+}
+
+fn is_old_dir(path: &std::path::Path) -> bool {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .map_or(false, |age| age > STALE_DIR_AGE)
+}
+
+#[cfg(target_os = "linux")]
+fn is_process_alive(pid: u32) -> Option<bool> {
+    Some(std::path::Path::new(&format!("/proc/{}", pid)).exists())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn is_process_alive(pid: u32) -> Option<bool> {
+    None
+}
+
+// Writes the buckets of a map task to the data file and their offsets to the index file.
+pub(crate) fn write_output_files<'a>(
+    dir: &str,
+    map_id: usize,
+    buckets: impl Iterator<Item = &'a [u8]>,
+) -> std::io::Result<()> {
+    let buckets = buckets.collect::<Vec<_>>();
+    write_output_files_with(dir, map_id, buckets.len(), |reduce_id, writer| {
+        writer.write_all(buckets[reduce_id])?;
+        Ok(buckets[reduce_id].len() as u64)
+    })
+}
+
+lazy_static! {
+    // held while the output files of a map task are renamed into place
+    static ref output_commit_lock: parking_lot::Mutex<()> = parking_lot::Mutex::new(());
+}
+
+// Same as write_output_files, but the content of each bucket is produced by `write_bucket`, which returns the number of bytes it wrote.
+// Both files are written to temporary files first and then renamed, so readers never see partial output.
+// Each attempt of a map task, like a speculative copy, writes its own temporary files, and the renames of
+// the data and index file of one attempt are never interleaved with those of another.
+pub(crate) fn write_output_files_with<F>(
+    dir: &str,
+    map_id: usize,
+    num_buckets: usize,
+    mut write_bucket: F,
+) -> std::io::Result<()>
+where
+    F: FnMut(usize, &mut dyn Write) -> std::io::Result<u64>,
+{
+    let data_path = format!("{}/{}.data", dir, map_id);
+    let index_path = format!("{}/{}.index", dir, map_id);
+    let attempt = Uuid::new_v4();
+    let tmp_data_path = format!("{}.{}.tmp", data_path, attempt);
+    let tmp_index_path = format!("{}.{}.tmp", index_path, attempt);
+    let mut data_file = BufWriter::new(fs::File::create(&tmp_data_path)?);
+    let mut index_file = BufWriter::new(fs::File::create(&tmp_index_path)?);
+    let mut offset = 0u64;
+    for reduce_id in 0..num_buckets {
+        index_file.write_all(&offset.to_le_bytes())?;
+        offset += write_bucket(reduce_id, &mut data_file)?;
+    }
+    index_file.write_all(&offset.to_le_bytes())?;
+    data_file.flush()?;
+    index_file.flush()?;
+    let _commit = output_commit_lock.lock();
+    fs::rename(tmp_data_path, data_path)?;
+    fs::rename(tmp_index_path, index_path)?;
+    Ok(())
+}
+
+// Reads the byte range of one bucket from the data file, using the offsets stored in the index file.
+pub(crate) fn read_output_range(
+    dir: &str,
+    map_id: usize,
+    reduce_id: usize,
+) -> std::io::Result<Vec<u8>> {
+    let mut index_file = fs::File::open(format!("{}/{}.index", dir, map_id))?;
+    index_file.seek(SeekFrom::Start(reduce_id as u64 * 8))?;
+    let mut offsets = [0u8; 16];
+    index_file.read_exact(&mut offsets)?;
+    let mut start = [0u8; 8];
+    let mut end = [0u8; 8];
+    start.copy_from_slice(&offsets[..8]);
+    end.copy_from_slice(&offsets[8..]);
+    let start = u64::from_le_bytes(start);
+    let end = u64::from_le_bytes(end);
+
+    let mut data_file = fs::File::open(format!("{}/{}.data", dir, map_id))?;
+    data_file.seek(SeekFrom::Start(start))?;
+    let mut bytes = vec![0; (end - start) as usize];
+    data_file.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_files_round_trip() {
+        let dir = std::env::temp_dir().join(format!("spark-shuffle-test-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let dir = dir.to_string_lossy().into_owned();
+        let buckets = vec![vec![1u8, 2, 3], vec![], vec![4u8; 100]];
+        write_output_files(&dir, 7, buckets.iter().map(|b| &b[..])).unwrap();
+        for (reduce_id, bucket) in buckets.iter().enumerate() {
+            assert_eq!(&read_output_range(&dir, 7, reduce_id).unwrap(), bucket);
+        }
+        assert!(read_output_range(&dir, 7, 3).is_err());
+        assert!(read_output_range(&dir, 8, 0).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn batches_end_after_max_bytes() {
+        let manager = ShuffleManager {
+            in_memory_threshold: std::usize::MAX,
+            ..Default::default()
+        };
+        for map_id in 0..3 {
+            manager
+                .write_map_output(0, map_id, vec![vec![map_id as u8; 10]])
+                .unwrap();
+        }
+        let batch =
+            |map_ids: &[usize], max_bytes| manager.get_map_outputs(0, 0, map_ids, max_bytes);
+        let block_len = 8 + 10;
+        assert_eq!(batch(&[0, 1, 2], 1000).len(), 3 * block_len);
+        assert_eq!(batch(&[0, 1, 2], block_len + 1).len(), 2 * block_len);
+        // a single block larger than the limit is still served
+        assert_eq!(
+            batch(&[2], 1),
+            [&10u64.to_le_bytes()[..], &[2u8; 10]].concat()
+        );
+        // outputs which can't be read are marked in place of their block
+        let missing = batch(&[5, 1], 1000);
+        assert_eq!(&missing[..8], &MISSING_BLOCK.to_le_bytes());
+        assert_eq!(missing.len(), 8 + block_len);
+    }
+
+    #[test]
+    fn sweep_removes_dirs_of_dead_processes() {
+        let root = std::env::temp_dir().join(format!("spark-sweep-test-{}", Uuid::new_v4()));
+        let dead = root.join("spark-local-dead");
+        let alive = root.join("spark-local-alive");
+        let other = root.join("other-dead");
+        for dir in &[&dead, &alive, &other] {
+            fs::create_dir_all(dir).unwrap();
+        }
+        // pids are bounded by pid_max, which is at most 2^22 on linux
+        fs::write(dead.join(PID_FILE), "4294967295").unwrap();
+        fs::write(other.join(PID_FILE), "4294967295").unwrap();
+        write_pid_file(&alive).unwrap();
+        sweep_stale_dirs(&root.to_string_lossy(), "spark-local-");
+        assert!(!dead.exists());
+        assert!(alive.exists());
+        assert!(other.exists());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -5,7 +5,6 @@ use super::*;
 //use std::hash::Hash;
 //use std::io::{BufWriter, Write};
 //use std::marker::PhantomData;
-use std::net::Ipv4Addr;
 use std::sync::Arc;
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -18,7 +17,10 @@ pub struct ShuffleMapTask {
     #[serde(with = "serde_traitobject")]
     pub dep: Arc<dyn ShuffleDependencyTrait>,
     pub partition: usize,
-    pub locs: Vec<Ipv4Addr>,
+    #[serde(with = "serde_traitobject")]
+    pub split: Box<dyn Split>,
+    pub locs: Vec<TaskLocation>,
+    pub attempt_id: usize,
 }
 
 impl ShuffleMapTask {
@@ -29,7 +31,8 @@ impl ShuffleMapTask {
         rdd: Arc<dyn RddBase>,
         dep: Arc<dyn ShuffleDependencyTrait>,
         partition: usize,
-        locs: Vec<Ipv4Addr>,
+        split: Box<dyn Split>,
+        locs: Vec<TaskLocation>,
     ) -> Self {
         ShuffleMapTask {
             task_id,
@@ -38,7 +41,9 @@ impl ShuffleMapTask {
             rdd,
             dep,
             partition,
+            split,
             locs,
+            attempt_id: 0,
         }
     }
 
@@ -59,7 +64,11 @@ impl TaskBase for ShuffleMapTask {
     fn get_task_id(&self) -> usize {
         self.task_id
     }
-    fn preferred_locations(&self) -> Vec<Ipv4Addr> {
+
+    fn get_attempt_id(&self) -> usize {
+        self.attempt_id
+    }
+    fn preferred_locations(&self) -> Vec<TaskLocation> {
         self.locs.clone()
     }
     fn generation(&self) -> Option<i64> {
@@ -71,7 +80,10 @@ impl TaskBase for ShuffleMapTask {
 
 impl Task for ShuffleMapTask {
     fn run(&self, id: usize) -> serde_traitobject::Box<dyn serde_traitobject::Any + Send + Sync> {
-        serde_traitobject::Box::new(self.dep.do_shuffle_task(self.rdd.clone(), self.partition))
-            as serde_traitobject::Box<dyn serde_traitobject::Any + Send + Sync>
+        serde_traitobject::Box::new(self.dep.do_shuffle_task(
+            self.rdd.clone(),
+            self.partition,
+            self.split.clone(),
+        )) as serde_traitobject::Box<dyn serde_traitobject::Any + Send + Sync>
     }
 }
@@ -1,6 +1,5 @@
 use super::*;
 //use parking_lot::Mutex;
-use std::collections::HashMap;
 use std::hash::Hash;
 //use std::marker::PhantomData;
 use std::sync::Arc;
@@ -9,13 +8,17 @@ use std::sync::Arc;
 use std::time::SystemTime;
 //use std::any::Any;
 
+// Reads the reduce partitions start..end of the shuffle. Without coalescing this is just the partition index,
+// coalesced splits are only built by the scheduler for the tasks of a stage, see Stage::split.
 #[derive(Clone, Serialize, Deserialize)]
 struct ShuffledRddSplit {
     index: usize,
+    start: usize,
+    end: usize,
 }
 impl ShuffledRddSplit {
-    fn new(index: usize) -> Self {
-        ShuffledRddSplit { index }
+    fn new(index: usize, start: usize, end: usize) -> Self {
+        ShuffledRddSplit { index, start, end }
     }
 }
 
@@ -63,6 +66,7 @@ where
         parent: Arc<RT>,
         aggregator: Arc<Aggregator<K, V, C>>,
         part: Box<dyn Partitioner>,
+        map_side_combine: bool,
     ) -> Self {
         let mut vals = RddVals::new(parent.get_context());
         let shuffle_id = vals.context.new_shuffle_id();
@@ -75,9 +79,10 @@ where
                     parent.get_rdd(),
                     aggregator.clone(),
                     part.clone(),
+                    map_side_combine,
                 ),
             )));
-        let vals = Arc::new(vals);
+        let vals = Arc::new(vals.track_shuffles());
         ShuffledRdd {
             parent,
             aggregator,
@@ -103,7 +108,7 @@ where
     }
     fn splits(&self) -> Vec<Box<dyn Split>> {
         (0..self.part.get_num_of_partitions())
-            .map(|x| Box::new(ShuffledRddSplit::new(x)) as Box<dyn Split>)
+            .map(|x| Box::new(ShuffledRddSplit::new(x, x, x + 1)) as Box<dyn Split>)
             .collect()
     }
     fn number_of_splits(&self) -> usize {
@@ -112,6 +117,9 @@ where
     fn partitioner(&self) -> Option<Box<dyn Partitioner>> {
         Some(self.part.clone())
     }
+    fn coalesced_split(&self, index: usize, start: usize, end: usize) -> Option<Box<dyn Split>> {
+        Some(Box::new(ShuffledRddSplit::new(index, start, end)))
+    }
     fn iterator_any(&self, split: Box<dyn Split>) -> Box<dyn Iterator<Item = Box<dyn AnyData>>> {
         info!("inside iterator_any shuffledrdd",);
         Box::new(
@@ -146,40 +154,35 @@ where
     //    }
     fn compute(&self, split: Box<dyn Split>) -> Box<dyn Iterator<Item = (K, C)>> {
         info!("compute inside shuffled rdd");
-        //        let combiners: Arc<Mutex<HashMap<K, Arc<Mutex<C>>>>> = Arc::new(Mutex::new(HashMap::new()));
-        // ArcMutex is being used in combiner because it needs to sent to closure.
-        // It has significant cost associated with it since it is called a lot of times.
-        // Since the combining operation is done only in sequential manner, I guess this can be eliminated.
-        let mut combiners: HashMap<K, Option<C>> = HashMap::new();
-        //        let mp_combiners = combiners.clone();
-        //        let mut combiners_lock = mp_combiners.lock();
-        let merge_pair = |(k, c): (K, C)| {
-            //            let old_c = combiners_lock.get(&k);
-            if let Some(old_c) = combiners.get_mut(&k) {
-                let old = old_c.take().unwrap();
-                let input = ((old, c),);
-                let output = self.aggregator.merge_combiners.call(input);
-                *old_c = Some(output);
-            } else {
-                //                combiners_lock.insert(k, Arc::new(Mutex::new(c)));
-                combiners.insert(k, Some(c));
+        // Fetched combiners are merged in a map which spills to disk when it grows beyond the memory threshold.
+        let aggregator = self.aggregator.clone();
+        let mut combiners = ExternalAppendOnlyMap::new(
+            move |c1: C, c2: C| aggregator.merge_combiners.call(((c1, c2),)),
+            env::config.shuffle_spill_memory,
+        );
+
+        let split = match split.downcast::<ShuffledRddSplit>() {
+            Ok(split) => split,
+            Err(_) => {
+                panic!("Got split object from different concrete type other than ShuffledRddSplit")
             }
         };
-
-        //TODO call fetch function after ShuffleFetcher is implemented
         let time = SystemTime::now();
         let fetcher = ShuffleFetcher;
-        fetcher.fetch(
-            self.vals.context.clone(),
-            self.shuffle_id,
-            split.get_index(),
-            merge_pair,
-        );
+        for reduce_id in split.start..split.end {
+            if let Err(failed) = fetcher.fetch(
+                self.vals.context.clone(),
+                self.shuffle_id,
+                reduce_id,
+                |(k, c): (K, C)| combiners.insert(k, c),
+            ) {
+                // aborts the task, the scheduler recomputes the missing map output
+                std::panic::resume_unwind(Box::new(failed));
+            }
+        }
         let dur = time.elapsed().unwrap().as_millis();
         info!("time taken for fetching {}", dur);
-        let dur = time.elapsed().unwrap().as_millis();
-        info!("time taken for converting to hashset {}", dur);
-        Box::new(combiners.into_iter().map(|(k, v)| (k, v.unwrap())))
+        combiners.into_combined_iter()
 
         //        let res = res.collect::<Vec<_>>();
         //        let log_output = format!("inside iterator shufflerdd {:?}", res.get(0));
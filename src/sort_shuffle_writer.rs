@@ -0,0 +1,365 @@
+use super::*;
+use std::collections::HashMap;
+use std::fs;
+use std::hash::Hash;
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+// Map side writer of a shuffle. Records are buffered until the memory budget is exceeded, then the buffer is
+// sorted by output partition and spilled to a temporary file. At the end the spills and the remaining buffer
+// are merged into the single data and index file of the map task.
+//
+// Each bucket is written in the same format as bincode uses for a Vec<(K, C)>: a u64 record count followed
+// by the serialized records. This is what makes merging cheap, since segments of different spills for the
+// same partition can be copied byte for byte and only the counts need to be added up.
+// The bucket is then compressed and checksummed as a single block, see shuffle_block.rs.
+// Combiners for the same key in different spills are not merged here, the reduce side merges them anyway.
+pub(crate) struct SortShuffleWriter<K: Data + Eq + Hash, V: Data, C: Data> {
+    shuffle_id: usize,
+    map_id: usize,
+    aggregator: Arc<Aggregator<K, V, C>>,
+    partitioner: Box<dyn Partitioner>,
+    num_partitions: usize,
+    map_side_combine: bool,
+    memory_budget: usize,
+    codec: CompressionCodec,
+    // buffer used with map side combining
+    combiners: HashMap<K, Option<C>>,
+    updates: usize,
+    avg_record_size: f64,
+    // buffer used without map side combining, holding the record count and serialized records of each partition
+    buffers: Vec<(u64, Vec<u8>)>,
+    buffered_bytes: usize,
+    spills: Vec<SpillFile>,
+}
+
+// A spilled run. Each partition has a segment of (offset, length, record count) in the file.
+struct SpillFile {
+    path: PathBuf,
+    segments: Vec<(u64, u64, u64)>,
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            info!("unable to remove spill file {:?}: {}", self.path, e);
+        }
+    }
+}
+
+impl<K: Data + Eq + Hash, V: Data, C: Data> SortShuffleWriter<K, V, C> {
+    pub fn new(
+        shuffle_id: usize,
+        map_id: usize,
+        aggregator: Arc<Aggregator<K, V, C>>,
+        partitioner: Box<dyn Partitioner>,
+        map_side_combine: bool,
+        memory_budget: usize,
+        codec: CompressionCodec,
+    ) -> Self {
+        let num_partitions = partitioner.get_num_of_partitions();
+        SortShuffleWriter {
+            shuffle_id,
+            map_id,
+            aggregator,
+            partitioner,
+            num_partitions,
+            map_side_combine,
+            memory_budget,
+            codec,
+            combiners: HashMap::new(),
+            updates: 0,
+            avg_record_size: 0.0,
+            buffers: (0..num_partitions).map(|_| (0, Vec::new())).collect(),
+            buffered_bytes: 0,
+            spills: Vec::new(),
+        }
+    }
+
+    pub fn insert(&mut self, k: K, v: V) -> io::Result<()> {
+        if self.map_side_combine {
+            self.updates += 1;
+            let sample = self.updates % SAMPLE_INTERVAL == 0;
+            if let Some(old_v) = self.combiners.get_mut(&k) {
+                let old = old_v.take().unwrap();
+                let output = self.aggregator.merge_value.call(((old, v),));
+                if sample {
+                    self.avg_record_size =
+                        update_avg(self.avg_record_size, record_size(&k, &output));
+                }
+                *old_v = Some(output);
+            } else {
+                let c = self.aggregator.create_combiner.call((v,));
+                if sample || self.combiners.is_empty() {
+                    self.avg_record_size = update_avg(self.avg_record_size, record_size(&k, &c));
+                }
+                self.combiners.insert(k, Some(c));
+            }
+            if sample && self.estimated_combiners_size() > self.memory_budget {
+                self.spill()?;
+            }
+        } else {
+            let partition = self.partitioner.get_partition(&k);
+            let c = self.aggregator.create_combiner.call((v,));
+            let buffer = &mut self.buffers[partition];
+            let len = buffer.1.len();
+            bincode::serialize_into(&mut buffer.1, &(k, c))
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+            buffer.0 += 1;
+            self.buffered_bytes += buffer.1.len() - len;
+            if self.buffered_bytes > self.memory_budget {
+                self.spill()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn estimated_combiners_size(&self) -> usize {
+        (self.avg_record_size * self.combiners.len() as f64) as usize
+    }
+
+    // Serializes the combiners into the per partition buffers, which groups them by partition.
+    fn drain_combiners(&mut self) -> io::Result<()> {
+        for (k, c) in self.combiners.drain() {
+            let partition = self.partitioner.get_partition(&k);
+            let buffer = &mut self.buffers[partition];
+            bincode::serialize_into(&mut buffer.1, &(k, c.unwrap()))
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+            buffer.0 += 1;
+        }
+        Ok(())
+    }
+
+    fn take_buffers(&mut self) -> Vec<(u64, Vec<u8>)> {
+        self.buffered_bytes = 0;
+        let num_partitions = self.num_partitions;
+        std::mem::replace(
+            &mut self.buffers,
+            (0..num_partitions).map(|_| (0, Vec::new())).collect(),
+        )
+    }
+
+    fn spill(&mut self) -> io::Result<()> {
+        self.drain_combiners()?;
+        let buffers = self.take_buffers();
+        let (path, file) = env::env.shuffle_manager.create_temp_file()?;
+        // registered before writing, so that the file is removed even if writing fails
+        self.spills.push(SpillFile {
+            path,
+            segments: Vec::with_capacity(self.num_partitions),
+        });
+        let spill = self.spills.last_mut().unwrap();
+        let mut writer = BufWriter::new(file);
+        let mut offset = 0;
+        for (count, bytes) in buffers {
+            writer.write_all(&bytes)?;
+            spill.segments.push((offset, bytes.len() as u64, count));
+            offset += bytes.len() as u64;
+        }
+        writer.flush()?;
+        info!(
+            "spilled {} bytes of shuffle {} map {} to {:?}",
+            offset, self.shuffle_id, self.map_id, spill.path
+        );
+        Ok(())
+    }
+
+    /// Writes the map output, merging all the spills with what is left in memory.
+    /// Returns the size in bytes and the record count of every bucket.
+    pub fn write(mut self) -> io::Result<(Vec<u64>, Vec<u64>)> {
+        self.drain_combiners()?;
+        let buffers = self.take_buffers();
+        let shuffle_manager = &env::env.shuffle_manager;
+        let codec = self.codec;
+        if self.spills.is_empty() {
+            let record_counts = buffers.iter().map(|(count, _)| *count).collect();
+            let buckets = buffers
+                .into_iter()
+                .map(|(count, bytes)| {
+                    let mut bucket = Vec::with_capacity(8 + bytes.len());
+                    write_block(codec, &mut bucket, |writer| {
+                        writer.write_all(&count.to_le_bytes())?;
+                        writer.write_all(&bytes)
+                    })?;
+                    Ok(bucket)
+                })
+                .collect::<io::Result<Vec<_>>>()?;
+            let sizes = buckets.iter().map(|bucket| bucket.len() as u64).collect();
+            shuffle_manager.write_map_output(self.shuffle_id, self.map_id, buckets)?;
+            return Ok((sizes, record_counts));
+        }
+
+        let mut spill_files = self
+            .spills
+            .iter()
+            .map(|spill| fs::File::open(&spill.path))
+            .collect::<io::Result<Vec<_>>>()?;
+        let spills = &self.spills;
+        let record_counts = (0..self.num_partitions)
+            .map(|partition| {
+                spills
+                    .iter()
+                    .map(|spill| spill.segments[partition].2)
+                    .sum::<u64>()
+                    + buffers[partition].0
+            })
+            .collect::<Vec<_>>();
+        let mut sizes = Vec::with_capacity(self.num_partitions);
+        shuffle_manager.write_map_output_files(
+            self.shuffle_id,
+            self.map_id,
+            self.num_partitions,
+            |partition, writer| {
+                let size = write_block(codec, writer, |writer| {
+                    writer.write_all(&record_counts[partition].to_le_bytes())?;
+                    for (spill, file) in spills.iter().zip(spill_files.iter_mut()) {
+                        let (offset, len, _) = spill.segments[partition];
+                        file.seek(SeekFrom::Start(offset))?;
+                        io::copy(&mut file.take(len), writer)?;
+                    }
+                    writer.write_all(&buffers[partition].1)
+                })?;
+                sizes.push(size);
+                Ok(size)
+            },
+        )?;
+        Ok((sizes, record_counts))
+    }
+}
+
+// Number of updates between two samples of the record size, when combining on the map side and in
+// the reduce side aggregation map.
+pub(crate) const SAMPLE_INTERVAL: usize = 256;
+
+pub(crate) fn record_size<K: Data, C: Data>(k: &K, c: &C) -> f64 {
+    bincode::serialized_size(&(k, c)).unwrap_or(0) as f64
+}
+
+pub(crate) fn update_avg(avg: f64, sample: f64) -> f64 {
+    if avg == 0.0 {
+        sample
+    } else {
+        0.8 * avg + 0.2 * sample
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const num_partitions: usize = 4;
+
+    fn writer(
+        shuffle_id: usize,
+        map_side_combine: bool,
+        memory_budget: usize,
+    ) -> SortShuffleWriter<i32, i32, i32> {
+        let aggregator = Aggregator::new(
+            Box::new(Fn!(|v: i32| v)),
+            Box::new(Fn!(|(c, v): (i32, i32)| c + v)),
+            Box::new(Fn!(|(c1, c2): (i32, i32)| c1 + c2)),
+        );
+        SortShuffleWriter::new(
+            shuffle_id,
+            0,
+            Arc::new(aggregator),
+            Box::new(HashPartitioner::<i32>::new(num_partitions)),
+            map_side_combine,
+            memory_budget,
+            CompressionCodec::Lz4,
+        )
+    }
+
+    fn records() -> Vec<(i32, i32)> {
+        (0..2000).map(|i| (i % 100, i)).collect()
+    }
+
+    // Writes the records and reads back the records of every bucket, checking the returned sizes and counts.
+    fn write_and_read(
+        mut writer: SortShuffleWriter<i32, i32, i32>,
+        min_spills: usize,
+    ) -> Vec<Vec<(i32, i32)>> {
+        let shuffle_id = writer.shuffle_id;
+        for (k, v) in records() {
+            writer.insert(k, v).unwrap();
+        }
+        assert!(
+            writer.spills.len() >= min_spills,
+            "{} spills",
+            writer.spills.len()
+        );
+        let (sizes, record_counts) = writer.write().unwrap();
+        (0..num_partitions)
+            .map(|partition| {
+                let block = env::env
+                    .shuffle_manager
+                    .get_map_output(shuffle_id, 0, partition)
+                    .unwrap();
+                assert_eq!(sizes[partition], block.len() as u64);
+                let records: Vec<(i32, i32)> =
+                    bincode::deserialize(&decode_block(&block).unwrap()).unwrap();
+                assert_eq!(record_counts[partition], records.len() as u64);
+                records
+            })
+            .collect()
+    }
+
+    fn assert_partitioned(buckets: &[Vec<(i32, i32)>]) {
+        let partitioner = HashPartitioner::<i32>::new(num_partitions);
+        for (partition, bucket) in buckets.iter().enumerate() {
+            for (k, _) in bucket {
+                assert_eq!(partitioner.get_partition(k), partition);
+            }
+        }
+    }
+
+    fn sums(records: impl Iterator<Item = (i32, i32)>) -> HashMap<i32, i32> {
+        let mut sums = HashMap::new();
+        for (k, v) in records {
+            *sums.entry(k).or_insert(0) += v;
+        }
+        sums
+    }
+
+    #[test]
+    fn buffered_records_are_written_without_spilling() {
+        let buckets = write_and_read(writer(1_000_001, false, std::usize::MAX), 0);
+        assert_partitioned(&buckets);
+        let mut written = buckets.into_iter().flatten().collect::<Vec<_>>();
+        written.sort();
+        let mut expected = records();
+        expected.sort();
+        assert_eq!(written, expected);
+    }
+
+    #[test]
+    fn spills_are_merged_without_combining() {
+        let buckets = write_and_read(writer(1_000_002, false, 1024), 2);
+        assert_partitioned(&buckets);
+        let mut written = buckets.into_iter().flatten().collect::<Vec<_>>();
+        written.sort();
+        let mut expected = records();
+        expected.sort();
+        assert_eq!(written, expected);
+    }
+
+    #[test]
+    fn combined_records_are_spilled_and_merged() {
+        let buckets = write_and_read(writer(1_000_003, true, 64), 2);
+        assert_partitioned(&buckets);
+        let written = buckets.into_iter().flatten().collect::<Vec<_>>();
+        // combiners for the same key in different spills are only merged on the reduce side
+        assert!(written.len() > 100 && written.len() < records().len());
+        assert_eq!(sums(written.into_iter()), sums(records().into_iter()));
+    }
+
+    #[test]
+    fn combined_records_are_written_once_per_key() {
+        let buckets = write_and_read(writer(1_000_004, true, std::usize::MAX), 0);
+        assert_partitioned(&buckets);
+        let written = buckets.into_iter().flatten().collect::<Vec<_>>();
+        assert_eq!(written.len(), 100);
+        assert_eq!(sums(written.into_iter()), sums(records().into_iter()));
+    }
+}
@@ -0,0 +1,110 @@
+use super::*;
+use std::collections::HashMap;
+use std::time::Duration;
+
+// Tasks running for less than this are never speculated, a copy would hardly finish any earlier.
+const min_speculation_threshold: Duration = Duration::from_millis(100);
+
+// How much longer than its finished siblings a task has to run before it gets a speculative copy.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct SpeculationConfig {
+    // fraction of the tasks of a stage which have to be finished first
+    pub quantile: f64,
+    // multiple of the median run time of the finished tasks
+    pub multiplier: f64,
+}
+
+impl SpeculationConfig {
+    // None unless SPARK_SPECULATION is set.
+    pub fn from_config() -> Option<Self> {
+        if !env::config.speculation {
+            return None;
+        }
+        Some(SpeculationConfig {
+            quantile: env::config.speculation_quantile,
+            multiplier: env::config.speculation_multiplier,
+        })
+    }
+}
+
+// Run times of the finished tasks of each stage in a job, used to spot the stragglers which get a
+// speculative copy, see SPARK_SPECULATION.
+#[derive(Debug, Default)]
+pub(crate) struct TaskDurations {
+    by_stage: HashMap<usize, Vec<Duration>>,
+}
+
+impl TaskDurations {
+    pub fn record(&mut self, stage_id: usize, duration: Duration) {
+        self.by_stage.entry(stage_id).or_default().push(duration);
+    }
+
+    pub fn finished(&self, stage_id: usize) -> usize {
+        self.by_stage.get(&stage_id).map_or(0, Vec::len)
+    }
+
+    // Run time after which a task of the stage is considered a straggler, multiplier times the
+    // median of the finished tasks. None until the quantile of its total_tasks finished.
+    pub fn threshold(
+        &self,
+        stage_id: usize,
+        total_tasks: usize,
+        config: SpeculationConfig,
+    ) -> Option<Duration> {
+        let durations = self.by_stage.get(&stage_id)?;
+        let min_finished = ((config.quantile * total_tasks as f64).floor() as usize).max(1);
+        if durations.len() < min_finished {
+            return None;
+        }
+        let mut durations = durations.clone();
+        durations.sort();
+        let median = durations[durations.len() / 2];
+        Some(
+            median
+                .mul_f64(config.multiplier)
+                .max(min_speculation_threshold),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const config: SpeculationConfig = SpeculationConfig {
+        quantile: 0.75,
+        multiplier: 1.5,
+    };
+
+    fn millis(durations: &[u64]) -> TaskDurations {
+        let mut task_durations = TaskDurations::default();
+        for &duration in durations {
+            task_durations.record(0, Duration::from_millis(duration));
+        }
+        task_durations
+    }
+
+    #[test]
+    fn no_threshold_before_quantile_finished() {
+        let durations = millis(&[1000, 1000]);
+        assert_eq!(durations.threshold(0, 4, config), None);
+        assert_eq!(durations.threshold(1, 4, config), None);
+        assert_eq!(durations.finished(0), 2);
+    }
+
+    #[test]
+    fn threshold_is_multiple_of_median() {
+        let durations = millis(&[1000, 400, 3000]);
+        let threshold = durations.threshold(0, 4, config);
+        assert_eq!(threshold, Some(Duration::from_millis(1500)));
+    }
+
+    #[test]
+    fn short_tasks_use_min_threshold() {
+        let durations = millis(&[1, 2, 3]);
+        assert_eq!(
+            durations.threshold(0, 3, config),
+            Some(min_speculation_threshold)
+        );
+    }
+}
@@ -14,8 +14,11 @@ pub struct Stage {
     pub is_shuffle_map: bool,
     pub rdd: Arc<dyn RddBase>,
     pub parents: Vec<Stage>,
-    pub output_locs: Vec<Vec<String>>,
+    pub output_locs: Vec<Vec<MapStatus>>,
     pub num_available_outputs: usize,
+    // ranges of reduce partitions read by each task when the scheduler coalesced small partitions of
+    // the stage's shuffle input. The rdd itself keeps its partitions, other stages may depend on them.
+    pub coalesced: Option<Vec<(usize, usize)>>,
 }
 
 impl PartialOrd for Stage {
@@ -64,6 +67,20 @@ impl Stage {
                 v
             },
             num_available_outputs: 0,
+            coalesced: None,
+        }
+    }
+
+    /// Split computed by the task of the given partition of the stage.
+    pub fn split(&self, partition: usize) -> Box<dyn Split> {
+        match &self.coalesced {
+            Some(ranges) => {
+                let (start, end) = ranges[partition];
+                shuffle_reader(self.get_rdd())
+                    .and_then(|(reader, _)| reader.coalesced_split(partition, start, end))
+                    .expect("coalesced stage without a shuffle reader")
+            }
+            None => self.rdd.splits()[partition].clone(),
         }
     }
 
@@ -79,15 +96,15 @@ impl Stage {
         }
     }
 
-    pub fn add_output_loc(&mut self, partition: usize, host: String) {
+    pub fn add_output_loc(&mut self, partition: usize, status: MapStatus) {
         info!(
             "adding loc for partition inside stage {} {:?}",
-            partition, host
+            partition, status.server_uri
         );
-        if !self.output_locs[partition].is_empty() {
+        if self.output_locs[partition].is_empty() {
             self.num_available_outputs += 1;
         }
-        self.output_locs[partition].push(host);
+        self.output_locs[partition].push(status);
     }
 
     pub fn remove_output_loc(&mut self, partition: usize, host: String) {
@@ -95,7 +112,7 @@ impl Stage {
         let new_vec = prev_vec
             .clone()
             .into_iter()
-            .filter(|x| x != &host)
+            .filter(|x| x.server_uri != host)
             .collect::<Vec<_>>();
         if (!prev_vec.is_empty()) && (new_vec.is_empty()) {
             self.num_available_outputs -= 1;
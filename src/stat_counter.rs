@@ -0,0 +1,182 @@
+use super::*;
+
+// Running summary statistics of a set of numbers, computed in a single pass using Welford's algorithm.
+// Per partition counters can be combined with `merge_stats`, so the whole RDD is summarized without a second pass.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StatCounter {
+    n: u64,
+    mu: f64,
+    m2: f64,
+    max_value: f64,
+    min_value: f64,
+}
+
+impl Default for StatCounter {
+    fn default() -> Self {
+        StatCounter::new()
+    }
+}
+
+impl StatCounter {
+    pub fn new() -> Self {
+        StatCounter {
+            n: 0,
+            mu: 0.0,
+            m2: 0.0,
+            max_value: std::f64::NEG_INFINITY,
+            min_value: std::f64::INFINITY,
+        }
+    }
+
+    pub fn from_values<I: IntoIterator<Item = f64>>(values: I) -> Self {
+        let mut stats = StatCounter::new();
+        for value in values {
+            stats.merge(value);
+        }
+        stats
+    }
+
+    /// Add a value into this StatCounter, updating the internal statistics.
+    pub fn merge(&mut self, value: f64) -> &mut Self {
+        let delta = value - self.mu;
+        self.n += 1;
+        self.mu += delta / self.n as f64;
+        self.m2 += delta * (value - self.mu);
+        self.max_value = self.max_value.max(value);
+        self.min_value = self.min_value.min(value);
+        self
+    }
+
+    /// Merge another StatCounter into this one, adding up the internal statistics.
+    pub fn merge_stats(&mut self, other: &StatCounter) -> &mut Self {
+        if other.n == 0 {
+            return self;
+        }
+        if self.n == 0 {
+            *self = other.clone();
+            return self;
+        }
+        let delta = other.mu - self.mu;
+        let n = self.n + other.n;
+        if other.n * 10 < self.n {
+            self.mu += delta * other.n as f64 / n as f64;
+        } else if self.n * 10 < other.n {
+            self.mu = other.mu - delta * self.n as f64 / n as f64;
+        } else {
+            self.mu = (self.mu * self.n as f64 + other.mu * other.n as f64) / n as f64;
+        }
+        self.m2 += other.m2 + (delta * delta * self.n as f64 * other.n as f64) / n as f64;
+        self.n = n;
+        self.max_value = self.max_value.max(other.max_value);
+        self.min_value = self.min_value.min(other.min_value);
+        self
+    }
+
+    pub fn count(&self) -> u64 {
+        self.n
+    }
+
+    pub fn mean(&self) -> f64 {
+        if self.n == 0 {
+            std::f64::NAN
+        } else {
+            self.mu
+        }
+    }
+
+    pub fn sum(&self) -> f64 {
+        self.n as f64 * self.mu
+    }
+
+    pub fn max(&self) -> f64 {
+        if self.n == 0 {
+            std::f64::NAN
+        } else {
+            self.max_value
+        }
+    }
+
+    pub fn min(&self) -> f64 {
+        if self.n == 0 {
+            std::f64::NAN
+        } else {
+            self.min_value
+        }
+    }
+
+    /// Return the population variance of the values.
+    pub fn variance(&self) -> f64 {
+        if self.n == 0 {
+            std::f64::NAN
+        } else {
+            self.m2 / self.n as f64
+        }
+    }
+
+    /// Return the sample variance, which corrects for bias in estimating the variance by dividing by N-1 instead of N.
+    pub fn sample_variance(&self) -> f64 {
+        if self.n <= 1 {
+            std::f64::NAN
+        } else {
+            self.m2 / (self.n - 1) as f64
+        }
+    }
+
+    /// Return the population standard deviation of the values.
+    pub fn stdev(&self) -> f64 {
+        self.variance().sqrt()
+    }
+
+    /// Return the sample standard deviation of the values, which corrects for bias in estimating the variance by dividing by N-1 instead of N.
+    pub fn sample_stdev(&self) -> f64 {
+        self.sample_variance().sqrt()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn single_pass_stats() {
+        let stats = StatCounter::from_values(vec![2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0]);
+        assert_eq!(stats.count(), 8);
+        assert_close(stats.mean(), 5.0);
+        assert_close(stats.sum(), 40.0);
+        assert_close(stats.variance(), 4.0);
+        assert_close(stats.stdev(), 2.0);
+        assert_close(stats.sample_variance(), 32.0 / 7.0);
+        assert_close(stats.min(), 2.0);
+        assert_close(stats.max(), 9.0);
+    }
+
+    #[test]
+    fn merged_stats_match_single_pass() {
+        let values = (0..100).map(|x| (x * x) as f64 / 7.0).collect::<Vec<_>>();
+        let expected = StatCounter::from_values(values.clone());
+        let mut merged = StatCounter::new();
+        for chunk in values.chunks(13) {
+            merged.merge_stats(&StatCounter::from_values(chunk.to_vec()));
+        }
+        merged.merge_stats(&StatCounter::new());
+        assert_eq!(merged.count(), expected.count());
+        assert_close(merged.mean(), expected.mean());
+        assert_close(merged.variance(), expected.variance());
+        assert_close(merged.min(), expected.min());
+        assert_close(merged.max(), expected.max());
+    }
+
+    #[test]
+    fn empty_stats() {
+        let stats = StatCounter::new();
+        assert_eq!(stats.count(), 0);
+        assert!(stats.mean().is_nan());
+        assert!(stats.variance().is_nan());
+        assert!(stats.min().is_nan());
+        assert_close(stats.sum(), 0.0);
+    }
+}
@@ -1,29 +1,84 @@
 use super::*;
 use downcast_rs::Downcast;
+use parking_lot::RwLock;
+use std::any::Any;
+use std::cell::RefCell;
 use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
 use std::net::Ipv4Addr;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Once;
+use std::time::{Duration, Instant};
+
+// A place where a task would rather run than on an arbitrary executor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TaskLocation {
+    // the partition is cached in the memory of the executor on this host
+    Executor(Ipv4Addr),
+    // the input of the partition is stored on this host, like a file split
+    Host(Ipv4Addr),
+}
+
+impl TaskLocation {
+    pub fn host(&self) -> Ipv4Addr {
+        match self {
+            TaskLocation::Executor(host) | TaskLocation::Host(host) => *host,
+        }
+    }
+}
 
 pub struct TasKContext {
     pub stage_id: usize,
     pub split_id: usize,
     pub attempt_id: usize,
+    pub run_id: usize,
 }
 
 impl TasKContext {
-    pub fn new(stage_id: usize, split_id: usize, attempt_id: usize) -> Self {
+    pub fn new(stage_id: usize, split_id: usize, attempt_id: usize, run_id: usize) -> Self {
         TasKContext {
             stage_id,
             split_id,
             attempt_id,
+            run_id,
         }
     }
+
+    /// Whether the job of the task was cancelled. Long running closures can check it to stop early.
+    pub fn is_cancelled(&self) -> bool {
+        is_job_cancelled(self.run_id)
+    }
+}
+
+// How long a cancelled job is remembered, long after its last task noticed the cancellation.
+const cancelled_job_retention: Duration = Duration::from_secs(600);
+
+lazy_static! {
+    // Jobs cancelled on the master or, on executors, through ExecutorMessage::CancelJob, with the
+    // time they were cancelled at.
+    static ref cancelled_jobs: RwLock<HashMap<usize, Instant>> = RwLock::new(HashMap::new());
+}
+
+pub(crate) fn cancel_job_locally(run_id: usize) {
+    let now = Instant::now();
+    let mut jobs = cancelled_jobs.write();
+    jobs.retain(|_, cancelled_at| now.duration_since(*cancelled_at) < cancelled_job_retention);
+    jobs.insert(run_id, now);
+}
+
+pub(crate) fn is_job_cancelled(run_id: usize) -> bool {
+    cancelled_jobs.read().contains_key(&run_id)
 }
 
 pub trait TaskBase: Downcast + Send + Sync {
     fn get_run_id(&self) -> usize;
     fn get_stage_id(&self) -> usize;
     fn get_task_id(&self) -> usize;
-    fn preferred_locations(&self) -> Vec<Ipv4Addr> {
+    // number of earlier attempts of the task which failed
+    fn get_attempt_id(&self) -> usize;
+    fn preferred_locations(&self) -> Vec<TaskLocation> {
         Vec::new()
     }
     fn generation(&self) -> Option<i64> {
@@ -81,13 +136,113 @@ pub enum TaskResult {
     //    //    #[serde(with = "serde_traitobject")]
     ResultTask(serde_traitobject::Box<dyn serde_traitobject::Any + Send + Sync>),
     ShuffleTask(serde_traitobject::Box<dyn serde_traitobject::Any + Send + Sync>),
+    // the task could not fetch the output of a map task
+    FetchFailed(FetchFailedVals),
+    // the task panicked or could not be deserialized
+    Failed(TaskFailure),
+}
+
+// Panic message and backtrace of a failed task, sent back to the master.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TaskFailure {
+    pub message: String,
+    pub backtrace: String,
+}
+
+impl TaskFailure {
+    pub fn new(message: String) -> Self {
+        TaskFailure {
+            message,
+            backtrace: String::new(),
+        }
+    }
+}
+
+impl fmt::Display for TaskFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.backtrace.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}\n{}", self.message, self.backtrace)
+        }
+    }
+}
+
+impl Error for TaskFailure {}
+
+thread_local! {
+    // backtrace of the last panic on this thread, recorded by the panic hook
+    static PANIC_BACKTRACE: RefCell<Option<String>> = RefCell::new(None);
+}
+
+static PANIC_HOOK: Once = Once::new();
+
+// Wraps the current panic hook so that the backtrace of a panicking task can be sent to the master.
+fn install_panic_hook() {
+    PANIC_HOOK.call_once(|| {
+        let default_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            let backtrace = format!("{:?}", backtrace::Backtrace::new());
+            PANIC_BACKTRACE.with(|b| *b.borrow_mut() = Some(backtrace));
+            default_hook(info);
+        }));
+    });
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        (*msg).to_string()
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        "Box<Any>".to_string()
+    }
+}
+
+impl TaskResult {
+    pub fn into_end_reason(self) -> (TastEndReason, Box<dyn Any + Send + Sync>) {
+        match self {
+            TaskResult::ResultTask(r) | TaskResult::ShuffleTask(r) => {
+                (TastEndReason::Success, r.into_any_send_sync())
+            }
+            TaskResult::FetchFailed(vals) => (TastEndReason::FetchFailed(vals), Box::new(())),
+            TaskResult::Failed(failure) => (TastEndReason::Error(Box::new(failure)), Box::new(())),
+        }
+    }
 }
 //
 impl TaskOption {
-    pub fn run(&self, id: usize) -> TaskResult {
-        match self {
+    pub fn run(&self) -> TaskResult {
+        let id = self.get_attempt_id();
+        if is_job_cancelled(self.get_run_id()) {
+            return TaskResult::Failed(TaskFailure::new(format!(
+                "job {} was cancelled",
+                self.get_run_id()
+            )));
+        }
+        install_panic_hook();
+        PANIC_BACKTRACE.with(|b| b.borrow_mut().take());
+        // Tasks which fail fetching shuffle outputs unwind with the FetchFailedVals as payload.
+        let result = panic::catch_unwind(AssertUnwindSafe(|| match self {
             TaskOption::ResultTask(tsk) => TaskResult::ResultTask(tsk.run(id)),
             TaskOption::ShuffleMapTask(tsk) => TaskResult::ShuffleTask(tsk.run(id)),
+        }));
+        match result {
+            Ok(result) => result,
+            Err(payload) => match payload.downcast::<FetchFailedVals>() {
+                Ok(failed) => {
+                    info!("task {} failed fetching {:?}", self.get_task_id(), failed);
+                    TaskResult::FetchFailed(*failed)
+                }
+                Err(payload) => {
+                    let message = panic_message(&*payload);
+                    error!("task {} panicked: {}", self.get_task_id(), message);
+                    let backtrace = PANIC_BACKTRACE
+                        .with(|b| b.borrow_mut().take())
+                        .unwrap_or_default();
+                    TaskResult::Failed(TaskFailure { message, backtrace })
+                }
+            },
         }
     }
     pub fn get_task_id(&self) -> usize {
@@ -96,6 +251,12 @@ impl TaskOption {
             TaskOption::ShuffleMapTask(tsk) => tsk.get_task_id(),
         }
     }
+    pub fn get_attempt_id(&self) -> usize {
+        match self {
+            TaskOption::ResultTask(tsk) => tsk.get_attempt_id(),
+            TaskOption::ShuffleMapTask(tsk) => tsk.get_attempt_id(),
+        }
+    }
     pub fn get_run_id(&self) -> usize {
         match self {
             TaskOption::ResultTask(tsk) => tsk.get_run_id(),
@@ -108,4 +269,10 @@ impl TaskOption {
             TaskOption::ShuffleMapTask(tsk) => tsk.get_stage_id(),
         }
     }
+    pub fn preferred_locations(&self) -> Vec<TaskLocation> {
+        match self {
+            TaskOption::ResultTask(tsk) => tsk.preferred_locations(),
+            TaskOption::ShuffleMapTask(tsk) => tsk.preferred_locations(),
+        }
+    }
 }
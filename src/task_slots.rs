@@ -0,0 +1,234 @@
+use super::*;
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::net::Ipv4Addr;
+use std::time::Duration;
+
+// How close an executor is to the data of a task, from the best to the worst.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum LocalityLevel {
+    Process,
+    Node,
+    Any,
+}
+
+// How long a task waits for a free slot at each level before settling for the next one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct LocalityWaits {
+    pub process: Duration,
+    pub node: Duration,
+}
+
+impl LocalityWaits {
+    pub fn from_config() -> Self {
+        LocalityWaits {
+            process: Duration::from_millis(env::config.locality_wait_process),
+            node: Duration::from_millis(env::config.locality_wait_node),
+        }
+    }
+
+    // Worst level a task may take after waiting for the given time, starting at the best level any
+    // executor offers it.
+    pub fn allowed_level(&self, best: LocalityLevel, waited: Duration) -> LocalityLevel {
+        let mut waited = waited;
+        if best == LocalityLevel::Process {
+            if waited < self.process {
+                return LocalityLevel::Process;
+            }
+            waited -= self.process;
+        }
+        if best <= LocalityLevel::Node && waited < self.node {
+            return LocalityLevel::Node;
+        }
+        LocalityLevel::Any
+    }
+}
+
+// Resources of an executor the backend runs tasks on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExecutorResources {
+    // None if the executor is only known by a host name, which is never matched with task locations
+    pub host: Option<Ipv4Addr>,
+    // number of tasks the executor runs at once
+    pub cores: usize,
+}
+
+#[derive(Clone, Debug)]
+struct ExecutorSlot {
+    resources: ExecutorResources,
+    // ids of the tasks running on the executor
+    tasks: HashSet<usize>,
+}
+
+impl ExecutorSlot {
+    fn free_slots(&self) -> usize {
+        self.resources.cores - self.tasks.len()
+    }
+
+    fn locality(&self, locations: &[TaskLocation]) -> LocalityLevel {
+        locations
+            .iter()
+            .filter(|location| Some(location.host()) == self.resources.host)
+            .map(|location| match location {
+                TaskLocation::Executor(_) => LocalityLevel::Process,
+                TaskLocation::Host(_) => LocalityLevel::Node,
+            })
+            .min()
+            .unwrap_or(LocalityLevel::Any)
+    }
+}
+
+// Task slots of all executors of a backend, one slot per core. Executors are identified by their
+// index in the list given by the backend.
+#[derive(Debug, Default)]
+pub(crate) struct ExecutorSlots {
+    executors: Vec<ExecutorSlot>,
+}
+
+impl ExecutorSlots {
+    pub fn new(executors: Vec<ExecutorResources>) -> Self {
+        ExecutorSlots {
+            executors: executors
+                .into_iter()
+                .map(|resources| ExecutorSlot {
+                    resources,
+                    tasks: HashSet::new(),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn total(&self) -> usize {
+        self.executors
+            .iter()
+            .map(|executor| executor.resources.cores)
+            .sum()
+    }
+
+    // Best level any executor not running the task yet offers to it, free or not.
+    pub fn best_locality(&self, task_id: usize, locations: &[TaskLocation]) -> LocalityLevel {
+        self.executors
+            .iter()
+            .filter(|executor| !executor.tasks.contains(&task_id))
+            .map(|executor| executor.locality(locations))
+            .min()
+            .unwrap_or(LocalityLevel::Any)
+    }
+
+    // Takes a slot on an executor at the given level or better, preferring the best level and then
+    // the most free slots. A speculative copy never runs where the task already runs. None if all
+    // of those executors are busy.
+    pub fn acquire(
+        &mut self,
+        task_id: usize,
+        locations: &[TaskLocation],
+        level: LocalityLevel,
+    ) -> Option<usize> {
+        let (index, executor) = self
+            .executors
+            .iter_mut()
+            .enumerate()
+            .filter(|(_, executor)| executor.free_slots() > 0)
+            .filter(|(_, executor)| !executor.tasks.contains(&task_id))
+            .filter(|(_, executor)| executor.locality(locations) <= level)
+            .min_by_key(|(_, executor)| {
+                (executor.locality(locations), Reverse(executor.free_slots()))
+            })?;
+        executor.tasks.insert(task_id);
+        Some(index)
+    }
+
+    pub fn release(&mut self, index: usize, task_id: usize) {
+        self.executors[index].tasks.remove(&task_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn executor(address: &str, cores: usize) -> ExecutorResources {
+        ExecutorResources {
+            host: address.parse().ok(),
+            cores,
+        }
+    }
+
+    #[test]
+    fn tasks_go_to_executors_with_most_free_slots() {
+        let mut slots = ExecutorSlots::new(vec![executor("a", 1), executor("b", 2)]);
+        assert_eq!(slots.total(), 3);
+        let any = LocalityLevel::Any;
+        assert_eq!(slots.acquire(0, &[], any), Some(1));
+        assert_eq!(slots.acquire(1, &[], any), Some(0));
+        assert_eq!(slots.acquire(2, &[], any), Some(1));
+        assert_eq!(slots.acquire(3, &[], any), None);
+        slots.release(0, 1);
+        assert_eq!(slots.acquire(3, &[], any), Some(0));
+        assert_eq!(slots.acquire(4, &[], any), None);
+    }
+
+    #[test]
+    fn speculative_copies_run_on_other_executors() {
+        let mut slots = ExecutorSlots::new(vec![executor("a", 2), executor("b", 1)]);
+        let any = LocalityLevel::Any;
+        assert_eq!(slots.acquire(0, &[], any), Some(0));
+        assert_eq!(slots.acquire(0, &[], any), Some(1));
+        assert_eq!(slots.acquire(0, &[], any), None);
+        slots.release(1, 0);
+        assert_eq!(slots.acquire(1, &[], any), Some(0));
+    }
+
+    #[test]
+    fn tasks_prefer_executors_close_to_their_data() {
+        let mut slots = ExecutorSlots::new(vec![
+            executor("10.0.0.1", 1),
+            executor("10.0.0.2", 4),
+            executor("10.0.0.3", 1),
+        ]);
+        let cached = [TaskLocation::Executor("10.0.0.1".parse().unwrap())];
+        let stored = [TaskLocation::Host("10.0.0.3".parse().unwrap())];
+        assert_eq!(slots.best_locality(0, &cached), LocalityLevel::Process);
+        assert_eq!(slots.best_locality(0, &stored), LocalityLevel::Node);
+        assert_eq!(slots.acquire(0, &cached, LocalityLevel::Process), Some(0));
+        // the only process local executor is busy, so the task has to wait or go elsewhere
+        assert_eq!(slots.acquire(1, &cached, LocalityLevel::Process), None);
+        assert_eq!(slots.acquire(1, &cached, LocalityLevel::Node), None);
+        assert_eq!(slots.acquire(1, &cached, LocalityLevel::Any), Some(1));
+        assert_eq!(slots.acquire(2, &stored, LocalityLevel::Node), Some(2));
+        assert_eq!(slots.acquire(3, &stored, LocalityLevel::Node), None);
+    }
+
+    #[test]
+    fn allowed_level_widens_after_each_wait() {
+        let waits = LocalityWaits {
+            process: Duration::from_millis(100),
+            node: Duration::from_millis(200),
+        };
+        let ms = Duration::from_millis;
+        assert_eq!(
+            waits.allowed_level(LocalityLevel::Process, ms(99)),
+            LocalityLevel::Process
+        );
+        assert_eq!(
+            waits.allowed_level(LocalityLevel::Process, ms(100)),
+            LocalityLevel::Node
+        );
+        assert_eq!(
+            waits.allowed_level(LocalityLevel::Process, ms(300)),
+            LocalityLevel::Any
+        );
+        assert_eq!(
+            waits.allowed_level(LocalityLevel::Node, ms(199)),
+            LocalityLevel::Node
+        );
+        assert_eq!(
+            waits.allowed_level(LocalityLevel::Node, ms(200)),
+            LocalityLevel::Any
+        );
+        assert_eq!(
+            waits.allowed_level(LocalityLevel::Any, ms(0)),
+            LocalityLevel::Any
+        );
+    }
+}
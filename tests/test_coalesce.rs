@@ -0,0 +1,69 @@
+use native_spark::*;
+#[macro_use]
+extern crate serde_closure;
+
+// The configuration is read once per process, so every test of this binary enables coalescing
+// before the first context is created.
+fn coalescing_context() -> Context {
+    std::env::set_var("SPARK_SHUFFLE_COALESCE_PARTITIONS", "true");
+    Context::new("local").unwrap()
+}
+
+#[test]
+fn test_join_after_coalesced_collect() {
+    let sc = coalescing_context();
+    let a = sc
+        .parallelize((0..20).map(|i| (i % 5, i)).collect::<Vec<_>>(), 4)
+        .reduce_by_key(Fn!(|(x, y): (i32, i32)| x + y), 8);
+    let b = sc.parallelize((0..5).map(|i| (i, i * 10)).collect::<Vec<_>>(), 2);
+    // reads a through a one-to-one dependency, so a has to keep its partitions
+    let joined = a.join(b, 8);
+
+    let mut sums = a.collect().unwrap();
+    sums.sort();
+    assert_eq!(a.number_of_splits(), 8);
+    let mut res = joined.collect().unwrap();
+    res.sort();
+    sc.drop_executors();
+
+    assert_eq!(sums, vec![(0, 30), (1, 34), (2, 38), (3, 42), (4, 46)]);
+    let expected = sums
+        .into_iter()
+        .map(|(k, sum)| (k, (sum, k * 10)))
+        .collect::<Vec<_>>();
+    assert_eq!(res, expected);
+}
+
+#[test]
+fn test_run_job_keeps_one_result_per_partition() {
+    let mut sc = coalescing_context();
+    let sums = sc
+        .parallelize((0..20).map(|i| (i % 5, i)).collect::<Vec<_>>(), 4)
+        .reduce_by_key(Fn!(|(x, y): (i32, i32)| x + y), 8);
+    let num_splits = sums.number_of_splits();
+    let counts = sc.run_job(
+        std::sync::Arc::new(sums),
+        Fn!(|iter: Box<dyn Iterator<Item = (i32, i32)>>| iter.count()),
+    );
+    let counts = counts.unwrap();
+    sc.drop_executors();
+
+    // the final stage is never coalesced, each partition has its own result
+    assert_eq!(counts.len(), num_splits);
+    assert_eq!(counts.iter().sum::<usize>(), 5);
+}
+
+#[test]
+fn test_coalesced_map_stage() {
+    let sc = coalescing_context();
+    let counts = sc
+        .parallelize((0..100).map(|i| (i % 10, 1)).collect::<Vec<_>>(), 4)
+        .reduce_by_key(Fn!(|(x, y): (i32, i32)| x + y), 8)
+        .map(Fn!(|(k, count): (i32, i32)| (k % 2, count)))
+        .reduce_by_key(Fn!(|(x, y): (i32, i32)| x + y), 3);
+    let mut res = counts.collect().unwrap();
+    res.sort();
+    sc.drop_executors();
+
+    assert_eq!(res, vec![(0, 50), (1, 50)]);
+}
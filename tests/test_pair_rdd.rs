@@ -1,9 +1,10 @@
 use native_spark::*;
+#[macro_use]
 extern crate serde_closure;
 
 #[test]
 fn test_group_by() {
-    let sc = Context::new("local");
+    let sc = Context::new("local").unwrap();
     let vec = vec![
         ("x".to_string(), 1),
         ("x".to_string(), 2),
@@ -23,7 +24,7 @@ fn test_group_by() {
     ];
     let r = sc.make_rdd(vec, 4);
     let g = r.group_by_key(4);
-    let mut res = g.collect();
+    let mut res = g.collect().unwrap();
     res.sort();
     println!("res {:?}", res);
     sc.drop_executors();
@@ -36,7 +37,7 @@ fn test_group_by() {
 
 #[test]
 fn test_join() {
-    let sc = Context::new("local");
+    let sc = Context::new("local").unwrap();
     let col1 = vec![
         (1, ("A".to_string(), "B".to_string())),
         (2, ("C".to_string(), "D".to_string())),
@@ -54,7 +55,7 @@ fn test_join() {
     ];
     let col2 = sc.parallelize(col2, 4);
     let inner_joined_rdd = col2.join(col1.clone(), 4);
-    let mut res = inner_joined_rdd.collect();
+    let mut res = inner_joined_rdd.collect().unwrap();
     println!("res {:?}", res);
     res.sort();
     sc.drop_executors();
@@ -70,4 +71,230 @@ fn test_join() {
         .map(|tuple| (tuple.0, (tuple.1.to_string(), (tuple.2.to_string(), tuple.3.to_string()))))
         .collect::<Vec<_>>();
     assert_eq!(expected, res);
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_count_by_key() {
+    let sc = Context::new("local").unwrap();
+    let vec = vec![
+        ("x".to_string(), 1),
+        ("y".to_string(), 2),
+        ("x".to_string(), 3),
+        ("z".to_string(), 4),
+        ("x".to_string(), 5),
+    ];
+    let counts = sc.make_rdd(vec, 2).count_by_key().unwrap();
+    sc.drop_executors();
+
+    let mut res = counts.into_iter().collect::<Vec<_>>();
+    res.sort();
+    let expected = vec![
+        ("x".to_string(), 3),
+        ("y".to_string(), 1),
+        ("z".to_string(), 1),
+    ];
+    assert_eq!(expected, res);
+}
+
+#[test]
+fn test_lookup() {
+    let sc = Context::new("local").unwrap();
+    let vec = vec![
+        ("x".to_string(), 1),
+        ("y".to_string(), 2),
+        ("x".to_string(), 3),
+    ];
+    let r = sc.make_rdd(vec, 2);
+    let mut res = r.lookup("x".to_string()).unwrap();
+    res.sort();
+    let grouped = r.group_by_key(3).lookup("y".to_string()).unwrap();
+    let missing = r.lookup("z".to_string()).unwrap();
+    sc.drop_executors();
+
+    assert_eq!(res, vec![1, 3]);
+    assert_eq!(grouped, vec![vec![2]]);
+    assert!(missing.is_empty());
+}
+
+#[test]
+fn test_aggregate_by_key() {
+    let sc = Context::new("local").unwrap();
+    let vec = vec![
+        ("x".to_string(), 1),
+        ("x".to_string(), 2),
+        ("y".to_string(), 3),
+        ("x".to_string(), 4),
+        ("y".to_string(), 5),
+        ("z".to_string(), 6),
+    ];
+    let r = sc.make_rdd(vec, 3);
+    // (sum, count) of the values for each key
+    let mut aggregated = r
+        .aggregate_by_key(
+            (0, 0),
+            Fn!(|((sum, count), v): ((i32, i32), i32)| (sum + v, count + 1)),
+            Fn!(|((s1, c1), (s2, c2)): ((i32, i32), (i32, i32))| (s1 + s2, c1 + c2)),
+            2,
+        )
+        .collect()
+        .unwrap();
+    aggregated.sort();
+    let mut folded = r
+        .fold_by_key(0, Fn!(|(a, b): (i32, i32)| a + b), 2)
+        .collect()
+        .unwrap();
+    folded.sort();
+    sc.drop_executors();
+
+    let expected = vec![
+        ("x".to_string(), (7, 3)),
+        ("y".to_string(), (8, 2)),
+        ("z".to_string(), (6, 1)),
+    ];
+    assert_eq!(expected, aggregated);
+    let expected = vec![
+        ("x".to_string(), 7),
+        ("y".to_string(), 8),
+        ("z".to_string(), 6),
+    ];
+    assert_eq!(expected, folded);
+}
+
+#[test]
+fn test_join_preserves_partitioning() {
+    let sc = Context::new("local").unwrap();
+    let col1 = vec![
+        (1, "A".to_string()),
+        (2, "B".to_string()),
+        (3, "C".to_string()),
+        (1, "D".to_string()),
+    ];
+    let col2 = vec![(1, 10), (2, 20), (2, 5), (4, 40)];
+    let left = sc
+        .parallelize(col1, 2)
+        .group_by_key(3)
+        .map_values(std::sync::Arc::new(Fn!(|v: Vec<String>| v.len())));
+    let right = sc
+        .parallelize(col2, 2)
+        .reduce_by_key(Fn!(|(a, b): (i32, i32)| a + b), 3)
+        .filter(Fn!(|(_, v): &(i32, i32)| *v > 0));
+    // both sides keep the hash partitioner of their shuffle, so the join reads them without another shuffle
+    assert!(left.partitioner().is_some());
+    assert!(right.partitioner().is_some());
+    let joined = left.join(right, 3);
+    // the join maps the values of the cogrouped rdd, which has one dependency for each side
+    let mut rdd = joined.get_rdd_base();
+    while rdd.get_dependencies().len() == 1 {
+        let parent = match &rdd.get_dependencies()[0] {
+            Dependency::OneToOneDependency(dep) => dep.get_rdd_base(),
+            _ => panic!("expected the join to map the values of the cogrouped rdd"),
+        };
+        rdd = parent;
+    }
+    let deps = rdd.get_dependencies();
+    assert_eq!(deps.len(), 2);
+    for dep in deps {
+        match dep {
+            Dependency::OneToOneDependency(_) => {}
+            _ => panic!("expected a pre-partitioned side to be read without a shuffle"),
+        }
+    }
+
+    let mut res = joined.collect().unwrap();
+    res.sort();
+    sc.drop_executors();
+
+    assert_eq!(res, vec![(1, (2, 10)), (2, (1, 25))]);
+}
+
+#[test]
+fn test_partition_by_fn_partitioner() {
+    let sc = Context::new("local").unwrap();
+    let vec = (0..10).map(|i| (i, i * 10)).collect::<Vec<_>>();
+    let partitioner = FnPartitioner::<i32>::new(2, "even_odd", Fn!(|k: &i32| (*k % 2) as usize));
+    let partitioned = sc
+        .parallelize(vec, 3)
+        .partition_by(Box::new(partitioner.clone()) as Box<dyn Partitioner>);
+    let parts = partitioned
+        .map_partitions(
+            Fn!(|iter: Box<dyn Iterator<Item = (i32, i32)>>| {
+                let mut keys = iter.map(|(k, _)| k).collect::<Vec<_>>();
+                keys.sort();
+                Box::new(std::iter::once(keys)) as Box<dyn Iterator<Item = Vec<i32>>>
+            }),
+            true,
+        )
+        .collect()
+        .unwrap();
+
+    // joining with an RDD partitioned by an equal FnPartitioner reads both sides without a shuffle
+    let other = sc
+        .parallelize(vec![(1, "a".to_string()), (4, "b".to_string())], 2)
+        .group_by_key_using_partitioner(Box::new(partitioner.clone()) as Box<dyn Partitioner>);
+    let mut joined = partitioned
+        .cogroup(other, Box::new(partitioner) as Box<dyn Partitioner>)
+        .collect()
+        .unwrap()
+        .into_iter()
+        .filter(|(_, (_, ws))| !ws.is_empty())
+        .map(|(k, (vs, _))| (k, vs))
+        .collect::<Vec<_>>();
+    joined.sort();
+    sc.drop_executors();
+
+    assert_eq!(parts, vec![vec![0, 2, 4, 6, 8], vec![1, 3, 5, 7, 9]]);
+    assert_eq!(joined, vec![(1, vec![10]), (4, vec![40])]);
+}
+
+#[test]
+fn test_shuffle_reused_across_jobs() {
+    let sc = Context::new("local").unwrap();
+    let pairs = sc.parallelize((0..100).map(|i| (i % 10, 1)).collect::<Vec<_>>(), 4);
+    let counts = pairs.reduce_by_key(Fn!(|(a, b): (i32, i32)| a + b), 3);
+    let mut first = counts.collect().unwrap();
+    // the second job finds the map outputs of the first one
+    let mut second = counts.collect().unwrap();
+    sc.drop_executors();
+    first.sort();
+    second.sort();
+    assert_eq!(first, (0..10).map(|k| (k, 10)).collect::<Vec<_>>());
+    assert_eq!(first, second);
+}
+
+#[test]
+fn test_skew_join() {
+    let mut sc = Context::new("local").unwrap();
+    // key 1 is hot, its rows are split over several reduce tasks even when they alternate with cold keys
+    let mut col1 = (0..20)
+        .flat_map(|i| vec![(1, i), (100 + i, i)])
+        .collect::<Vec<_>>();
+    col1.push((2, 100));
+    col1.push((3, 200));
+    let col2 = vec![
+        (1, "A".to_string()),
+        (1, "B".to_string()),
+        (2, "C".to_string()),
+        (4, "D".to_string()),
+    ];
+    let left = sc.parallelize(col1, 3);
+    let right = sc.parallelize(col2, 2);
+    let joined = left.skew_join(right.clone(), 8, 5, 3).unwrap();
+    let mut res = joined.collect().unwrap();
+    res.sort();
+    let hot_rows = sc
+        .run_job(
+            std::sync::Arc::new(joined),
+            Fn!(|iter: Box<dyn Iterator<Item = (i32, (i32, String))>>| {
+                iter.filter(|(k, _)| *k == 1).count()
+            }),
+        )
+        .unwrap();
+    let mut expected = left.join(right, 8).collect().unwrap();
+    expected.sort();
+    sc.drop_executors();
+
+    assert_eq!(res.len(), 41);
+    assert_eq!(res, expected);
+    assert_eq!(hot_rows.iter().sum::<usize>(), 40);
+    assert!(hot_rows.iter().filter(|&&rows| rows > 0).count() > 1);
+}
@@ -5,11 +5,11 @@ extern crate serde_closure;
 #[test]
 fn test_make_rdd() {
     // for distributed mode, use Context::new("distributed")
-    let sc = Context::new("local");
+    let sc = Context::new("local").unwrap();
     let col = sc.make_rdd((0..10).collect::<Vec<_>>(), 32);
     //Fn! will make the closures serializable. It is necessary. use serde_closure version 0.1.3.
     let vec_iter = col.map(Fn!(|i| (0..i).collect::<Vec<_>>()));
-    let res = vec_iter.collect();
+    let res = vec_iter.collect().unwrap();
     sc.drop_executors();
 
     let expected = (0..10)
@@ -21,37 +21,290 @@ fn test_make_rdd() {
 
 #[test]
 fn test_take() {
-    let sc = Context::new("local");
+    let sc = Context::new("local").unwrap();
     let col1 = vec![1, 2, 3, 4, 5, 6];
     let col1_rdd = sc.parallelize(col1, 4);
 
-    let taken_1 = col1_rdd.take(1);
+    let taken_1 = col1_rdd.take(1).unwrap();
     assert_eq!(taken_1.len(), 1);
 
-    let taken_3 = col1_rdd.take(3);
+    let taken_3 = col1_rdd.take(3).unwrap();
     assert_eq!(taken_3.len(), 3);
 
-    let taken_5 = col1_rdd.take(7);
+    let taken_5 = col1_rdd.take(7).unwrap();
     assert_eq!(taken_5.len(), 6);
 
     let col2: Vec<i32> = vec![];
     let col2_rdd = sc.parallelize(col2, 4);
-    let taken_0 = col2_rdd.take(1);
+    let taken_0 = col2_rdd.take(1).unwrap();
     assert!(taken_0.is_empty());
 }
 
 #[test]
 fn test_first() {
-    let sc = Context::new("local");
+    let sc = Context::new("local").unwrap();
     let col1 = vec![1, 2, 3, 4];
     let col1_rdd = sc.parallelize(col1, 4);
 
     let taken_1 = col1_rdd.first();
     assert!(taken_1.is_ok());
 
-    // TODO: uncomment when it returns a proper error instead of panicking
-    // let col2: Vec<i32> = vec![];
-    // let col2_rdd = sc.parallelize(col2, 4);
-    // let taken_0 = col2_rdd.first();
-    // assert!(taken_0.is_err());
+    let col2: Vec<i32> = vec![];
+    let col2_rdd = sc.parallelize(col2, 4);
+    let taken_0 = col2_rdd.first();
+    assert!(taken_0.is_err());
+}
+
+#[test]
+fn test_stats() {
+    let sc = Context::new("local").unwrap();
+    let col = vec![2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0];
+    let rdd = sc.parallelize(col, 3);
+    let stats = rdd.stats().unwrap();
+    sc.drop_executors();
+
+    assert_eq!(stats.count(), 8);
+    assert!((stats.mean() - 5.0).abs() < 1e-9);
+    assert!((stats.sum() - 40.0).abs() < 1e-9);
+    assert!((stats.stdev() - 2.0).abs() < 1e-9);
+    assert!((stats.min() - 2.0).abs() < 1e-9);
+    assert!((stats.max() - 9.0).abs() < 1e-9);
+}
+
+#[test]
+fn test_histogram() {
+    let sc = Context::new("local").unwrap();
+    let rdd = sc.parallelize((0..=100).collect::<Vec<i32>>(), 4);
+    let (buckets, counts) = rdd.histogram(4).unwrap();
+    assert_eq!(buckets, vec![0.0, 25.0, 50.0, 75.0, 100.0]);
+    assert_eq!(counts, vec![25, 25, 25, 26]);
+
+    let counts = rdd
+        .histogram_with_buckets(vec![0.0, 10.0, 50.0, 200.0], false)
+        .unwrap();
+    sc.drop_executors();
+    assert_eq!(counts, vec![10, 40, 51]);
+}
+
+#[test]
+fn test_histogram_of_constant_rdd() {
+    let sc = Context::new("local").unwrap();
+    let rdd = sc.parallelize(vec![7; 10], 3);
+    let (buckets, counts) = rdd.histogram(4).unwrap();
+    sc.drop_executors();
+    assert_eq!(buckets, vec![7.0, 7.0]);
+    assert_eq!(counts, vec![10]);
+}
+
+#[test]
+fn test_histogram_with_invalid_buckets() {
+    let sc = Context::new("local").unwrap();
+    let rdd = sc.parallelize((0..10).collect::<Vec<i32>>(), 2);
+    let invalid = vec![
+        rdd.histogram(0).map(|_| ()),
+        rdd.histogram_with_buckets(vec![1.0], false).map(|_| ()),
+        rdd.histogram_with_buckets(vec![5.0, 1.0, 10.0], false)
+            .map(|_| ()),
+        rdd.histogram_with_buckets(vec![1.0, 5.0, 5.0], false)
+            .map(|_| ()),
+        rdd.histogram_with_buckets(vec![1.0, std::f64::NAN, 10.0], false)
+            .map(|_| ()),
+    ];
+    sc.drop_executors();
+    for res in invalid {
+        match res {
+            Err(Error::UnsupportedOperation(_)) => {}
+            res => panic!("expected an unsupported operation, got {:?}", res),
+        }
+    }
+}
+
+#[test]
+fn test_count_by_value() {
+    let sc = Context::new("local").unwrap();
+    let rdd = sc.parallelize(vec![1, 2, 2, 3, 3, 3], 3);
+    let counts = rdd.count_by_value().unwrap();
+    sc.drop_executors();
+
+    let mut res = counts.into_iter().collect::<Vec<_>>();
+    res.sort();
+    assert_eq!(res, vec![(1, 1), (2, 2), (3, 3)]);
+}
+
+#[test]
+fn test_top_and_take_ordered() {
+    let sc = Context::new("local").unwrap();
+    let rdd = sc.parallelize(vec![5, 1, 9, 3, 7, 2, 8, 6, 4], 3);
+    let top = rdd.top(3).unwrap();
+    let smallest = rdd
+        .take_ordered(4, Fn!(|a: &i32, b: &i32| a.cmp(b)))
+        .unwrap();
+    let none = rdd
+        .take_ordered(0, Fn!(|a: &i32, b: &i32| a.cmp(b)))
+        .unwrap();
+    sc.drop_executors();
+
+    assert_eq!(top, vec![9, 8, 7]);
+    assert_eq!(smallest, vec![1, 2, 3, 4]);
+    assert!(none.is_empty());
+}
+
+#[test]
+fn test_filter() {
+    let sc = Context::new("local").unwrap();
+    let rdd = sc.parallelize((0..20).collect::<Vec<_>>(), 4);
+    let res = rdd.filter(Fn!(|x: &i32| x % 3 == 0)).collect().unwrap();
+    sc.drop_executors();
+
+    assert_eq!(res, vec![0, 3, 6, 9, 12, 15, 18]);
+}
+
+#[test]
+fn test_map_partitions() {
+    let sc = Context::new("local").unwrap();
+    let rdd = sc.parallelize((1..=10).collect::<Vec<_>>(), 2);
+    let sums = rdd
+        .map_partitions(
+            Fn!(|iter: Box<dyn Iterator<Item = i32>>| {
+                Box::new(std::iter::once(iter.sum::<i32>())) as Box<dyn Iterator<Item = i32>>
+            }),
+            false,
+        )
+        .collect()
+        .unwrap();
+    sc.drop_executors();
+
+    assert_eq!(sums, vec![15, 40]);
+}
+
+#[test]
+fn test_panicking_task_is_retried() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    static PANICKED: AtomicBool = AtomicBool::new(false);
+    let sc = Context::new("local").unwrap();
+    let rdd = sc.make_rdd((0..10).collect::<Vec<_>>(), 2);
+    // only the first attempt of the task holding 7 panics
+    let res = rdd
+        .map(Fn!(|i| {
+            if i == 7 && !PANICKED.swap(true, Ordering::SeqCst) {
+                panic!("first attempt fails");
+            }
+            i * 2
+        }))
+        .collect()
+        .unwrap();
+    assert_eq!(res, (0..10).map(|i| i * 2).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_panicking_task_aborts_job() {
+    let sc = Context::new("local").unwrap();
+    let rdd = sc.make_rdd((0..10).collect::<Vec<_>>(), 2);
+    let res = rdd
+        .map(Fn!(|i: i32| {
+            if i == 7 {
+                panic!("always fails");
+            }
+            i
+        }))
+        .collect();
+    match res {
+        Err(Error::TaskFailed { cause, .. }) => assert!(cause.contains("always fails")),
+        _ => panic!("expected the job to be aborted"),
+    }
+}
+
+#[test]
+fn test_job_timeout() {
+    let sc = Context::new("local").unwrap();
+    let slow = sc
+        .parallelize((0..4).collect::<Vec<_>>(), 4)
+        .map(Fn!(|i: i32| {
+            std::thread::sleep(std::time::Duration::from_secs(2));
+            i
+        }));
+    let res = slow
+        .collect_async()
+        .wait_timeout(std::time::Duration::from_millis(200));
+    match res {
+        Err(Error::JobTimedOut { .. }) => {}
+        _ => panic!("expected the job to time out"),
+    }
+}
+
+#[test]
+fn test_cancel_job() {
+    let mut sc = Context::new("local").unwrap();
+    let canceller = sc.clone();
+    std::thread::spawn(move || loop {
+        if !canceller.active_jobs().is_empty() {
+            canceller.cancel_all_jobs();
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    });
+    let rdd = sc.parallelize((0..4).collect::<Vec<_>>(), 4);
+    // the tasks only end once they see the job being cancelled
+    let res = sc.run_job_with_context(
+        rdd.get_rdd(),
+        Fn!(
+            |(ctx, iter): (TasKContext, Box<dyn Iterator<Item = i32>>)| {
+                while !ctx.is_cancelled() {
+                    std::thread::sleep(std::time::Duration::from_millis(10));
+                }
+                iter.count()
+            }
+        ),
+    );
+    match res {
+        Err(Error::JobCancelled(_)) => {}
+        _ => panic!("expected the job to be cancelled"),
+    }
+}
+
+#[test]
+fn test_async_actions() {
+    let sc = Context::new("local").unwrap();
+    let rdd = sc.parallelize((0..100).collect::<Vec<_>>(), 4);
+    let collected = rdd.collect_async();
+    let counted = rdd.map(Fn!(|i: i32| i * 2)).count_async();
+    assert_eq!(collected.wait().unwrap(), (0..100).collect::<Vec<_>>());
+    assert_eq!(counted.wait().unwrap(), 100);
+    assert_eq!(rdd.count().unwrap(), 100);
+
+    let slow = rdd.for_each_async(Fn!(|_i: i32| {
+        std::thread::sleep(std::time::Duration::from_millis(100))
+    }));
+    let job_id = slow.job_id();
+    while slow.progress().is_none() && !slow.is_done() {
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    slow.cancel();
+    match slow.wait() {
+        Err(Error::JobCancelled(id)) => assert_eq!(id, job_id),
+        _ => panic!("expected the job to be cancelled"),
+    }
+}
+
+#[test]
+fn test_concurrent_jobs_in_pools() {
+    let sc = Context::new("local").unwrap();
+    let handles = vec!["etl", "adhoc"]
+        .into_iter()
+        .map(|pool| {
+            let sc = sc.clone();
+            std::thread::spawn(move || {
+                sc.set_job_pool(Some(pool));
+                let rdd = sc.parallelize((0..100).collect::<Vec<_>>(), 8);
+                let doubled = rdd.map(Fn!(|i: i32| i * 2)).collect_async();
+                let count = rdd.count().unwrap();
+                (count, doubled.wait().unwrap())
+            })
+        })
+        .collect::<Vec<_>>();
+    for handle in handles {
+        let (count, doubled) = handle.join().unwrap();
+        assert_eq!(count, 100);
+        assert_eq!(doubled, (0..100).map(|i| i * 2).collect::<Vec<_>>());
+    }
 }